@@ -0,0 +1,250 @@
+use {
+    crate::{Event, Result, Test},
+    std::{
+        collections::{BTreeSet, HashMap},
+        env, fs, io,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+const TIMINGS_FILE: &str = "timings";
+const FAILED_FILE: &str = "failed";
+
+/// Default directory for state persisted between runs
+///
+/// This follows the XDG base directory specification.
+pub fn default_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("gtest-runner"))
+}
+
+/// Durations of tests from previous runs
+///
+/// Durations are keyed by the canonicalized path of the test executable and the test name.
+#[derive(Debug, Default)]
+pub struct Timings {
+    durations: HashMap<PathBuf, HashMap<String, Duration>>,
+}
+
+impl Timings {
+    /// Load timings from the cache directory
+    ///
+    /// A missing cache is treated as empty, and malformed entries are ignored.
+    pub fn load(dir: &Path) -> Result<Timings> {
+        let content = match fs::read(dir.join(TIMINGS_FILE)) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Timings::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut timings = Timings::default();
+        for line in content.lines() {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(ms), Some(executable), Some(test)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Ok(ms) = ms.parse() else {
+                continue;
+            };
+
+            timings
+                .durations
+                .entry(executable.into())
+                .or_default()
+                .insert(test.into(), Duration::from_millis(ms));
+        }
+
+        Ok(timings)
+    }
+
+    /// Store timings in the cache directory
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let mut content = String::new();
+        for (executable, tests) in &self.durations {
+            for (test, duration) in tests {
+                content += &format!(
+                    "{}\t{}\t{}\n",
+                    duration.as_millis(),
+                    executable.display(),
+                    test
+                );
+            }
+        }
+
+        write_atomically(&dir.join(TIMINGS_FILE), &content)
+    }
+
+    /// Record the durations of the given terminal test results
+    pub fn update(&mut self, results: &[Test]) {
+        for test in results {
+            if let (
+                Some(executable),
+                Event::Terminal {
+                    duration: Some(duration),
+                    ..
+                },
+            ) = (&test.executable, &test.event)
+            {
+                self.durations
+                    .entry(executable.clone())
+                    .or_default()
+                    .insert(test.testcase.clone(), *duration);
+            }
+        }
+    }
+
+    /// Estimator of the durations of the tests of an executable
+    ///
+    /// For tests without recorded duration the estimate is the mean duration of the other tests
+    /// of the same executable. The mean is computed once, so the estimator should be reused for
+    /// all tests of the executable.
+    pub fn estimator(&self, executable: &Path) -> impl Fn(&str) -> Duration + '_ {
+        let tests = self.durations.get(executable);
+        let mean = tests.map_or(Duration::from_millis(1), |tests| {
+            tests.values().sum::<Duration>() / u32::try_from(tests.len()).unwrap_or(u32::MAX)
+        });
+
+        move |test| {
+            tests
+                .and_then(|tests| tests.get(test))
+                .copied()
+                .unwrap_or(mean)
+        }
+    }
+}
+
+/// Tests which failed in previous runs
+///
+/// Tests are keyed by the canonicalized path of the test executable and the test name.
+#[derive(Debug, Default)]
+pub struct FailedTests {
+    tests: HashMap<PathBuf, BTreeSet<String>>,
+}
+
+impl FailedTests {
+    /// Load failed tests from the cache directory
+    ///
+    /// A missing cache is treated as empty, and malformed entries are ignored.
+    pub fn load(dir: &Path) -> Result<FailedTests> {
+        let content = match fs::read(dir.join(FAILED_FILE)) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(FailedTests::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut failed = FailedTests::default();
+        for line in content.lines() {
+            if let Some((executable, test)) = line.rsplit_once('\t') {
+                failed
+                    .tests
+                    .entry(executable.into())
+                    .or_default()
+                    .insert(test.into());
+            }
+        }
+
+        Ok(failed)
+    }
+
+    /// Store failed tests in the cache directory
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let mut content = String::new();
+        for (executable, tests) in &self.tests {
+            for test in tests {
+                content += &format!("{}\t{}\n", executable.display(), test);
+            }
+        }
+
+        write_atomically(&dir.join(FAILED_FILE), &content)
+    }
+
+    /// Record the outcomes of the given terminal test results
+    ///
+    /// Failed tests are added, while tests which did not fail are removed. Tests without results
+    /// keep their previous state.
+    pub fn update(&mut self, results: &[Test]) {
+        for test in results {
+            if let (Some(executable), Event::Terminal { status, .. }) =
+                (&test.executable, &test.event)
+            {
+                let tests = self.tests.entry(executable.clone()).or_default();
+                if status.is_failed() {
+                    tests.insert(test.testcase.clone());
+                } else {
+                    tests.remove(&test.testcase);
+                }
+            }
+        }
+
+        self.tests.retain(|_, tests| !tests.is_empty());
+    }
+
+    /// Check whether a test failed in a previous run
+    pub fn contains(&self, executable: &Path, test: &str) -> bool {
+        self.tests
+            .get(executable)
+            .is_some_and(|tests| tests.contains(test))
+    }
+}
+
+/// Write a file so that concurrent readers never see partial content
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_timings() {
+    let dir = env::temp_dir().join(format!("gtest-runner-timings-{}", std::process::id()));
+
+    let test = |executable: &str, testcase: &str, ms| Test {
+        testcase: testcase.into(),
+        shard: None,
+        executable: Some(executable.into()),
+        event: Event::Terminal {
+            status: crate::Status::Ok,
+            log: vec![],
+            duration: Some(Duration::from_millis(ms)),
+        },
+    };
+
+    let mut timings = Timings::load(&dir).unwrap();
+    assert_eq!(
+        Duration::from_millis(1),
+        timings.estimator(Path::new("/exe1"))("A.A")
+    );
+
+    timings.update(&[test("/exe1", "A.A", 10), test("/exe1", "A.B", 30)]);
+    timings.save(&dir).unwrap();
+
+    let timings = Timings::load(&dir).unwrap();
+
+    let estimate = timings.estimator(Path::new("/exe1"));
+    assert_eq!(Duration::from_millis(10), estimate("A.A"));
+    assert_eq!(Duration::from_millis(30), estimate("A.B"));
+
+    // Unknown tests are estimated from known tests of the same executable.
+    assert_eq!(Duration::from_millis(20), estimate("A.C"));
+
+    // Unreadable entries are ignored.
+    fs::write(dir.join(TIMINGS_FILE), b"\xff\xfe\n5\t/exe1\tA.A\n").unwrap();
+    let timings = Timings::load(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        Duration::from_millis(5),
+        timings.estimator(Path::new("/exe1"))("A.A")
+    );
+}
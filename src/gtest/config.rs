@@ -0,0 +1,296 @@
+use {
+    crate::{Filter, Flaky, Ports, Rerun, Resource, Schedule, Stress, Timeouts},
+    std::{
+        ffi::{OsStr, OsString},
+        fs::canonicalize,
+        path::{Path, PathBuf},
+        process::Command,
+        time::Duration,
+    },
+};
+
+/// Settings overriding the run configuration for a single test executable
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// Filter used instead of the filter of the run.
+    pub filter: Option<Filter>,
+    /// Environment variables set in addition to those of the run.
+    pub env: Vec<(OsString, OsString)>,
+    /// Maximum duration of a single test, used instead of the timeout of the run.
+    pub timeout: Option<Duration>,
+    /// Maximum number of concurrent invocations of the test executable.
+    pub jobs: Option<usize>,
+    /// Arguments passed to every invocation of the test executable.
+    pub args: Vec<OsString>,
+}
+
+impl Overrides {
+    /// Apply `other` on top of these overrides
+    ///
+    /// Settings of `other` replace those set here, environment variables are added.
+    fn merge(&mut self, other: Overrides) {
+        self.filter = other.filter.or(self.filter.take());
+        self.env.extend(other.env);
+        self.timeout = other.timeout.or(self.timeout);
+        self.jobs = other.jobs.or(self.jobs);
+        if !other.args.is_empty() {
+            self.args = other.args;
+        }
+    }
+}
+
+/// Configuration of a test run
+///
+/// A configuration is created for a set of test executables with `RunConfig::new`, and further
+/// customized with builder methods, e.g.,
+///
+/// ```no_run
+/// # use gtest::RunConfig;
+/// let summary = gtest::run(
+///     &RunConfig::new(["./test-exe"]).jobs(4).repeat(2),
+///     &mut [],
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    pub(crate) executables: Vec<PathBuf>,
+    pub(crate) filter: Filter,
+    pub(crate) jobs: usize,
+    pub(crate) repeat: u64,
+    pub(crate) flaky: Flaky,
+    pub(crate) stress: Option<Stress>,
+    pub(crate) schedule: Schedule,
+    pub(crate) env: Vec<(OsString, OsString)>,
+    pub(crate) working_dir: Option<PathBuf>,
+    pub(crate) isolate_working_dir: bool,
+    pub(crate) wrapper: Vec<OsString>,
+    pub(crate) ports: Option<Ports>,
+    pub(crate) resources: Vec<Resource>,
+    pub(crate) serial: Vec<String>,
+    pub(crate) overrides: Vec<(PathBuf, Overrides)>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) cache_dir: Option<PathBuf>,
+    pub(crate) rerun: Rerun,
+}
+
+impl RunConfig {
+    /// Configuration running all tests of the given test executables
+    ///
+    /// By default tests are run with as many parallel jobs as there are processors available.
+    pub fn new<I, P>(executables: I) -> RunConfig
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        RunConfig {
+            executables: executables.into_iter().map(Into::into).collect(),
+            filter: Filter::default(),
+            jobs: std::thread::available_parallelism().map_or(1, Into::into),
+            repeat: 0,
+            flaky: Flaky::default(),
+            stress: None,
+            schedule: Schedule::default(),
+            env: vec![],
+            working_dir: None,
+            isolate_working_dir: false,
+            wrapper: vec![],
+            ports: None,
+            resources: vec![],
+            serial: vec![],
+            overrides: vec![],
+            timeouts: Timeouts::default(),
+            cache_dir: None,
+            rerun: Rerun::default(),
+        }
+    }
+
+    /// Only run the tests selected by `filter`.
+    pub fn filter(mut self, filter: Filter) -> RunConfig {
+        self.filter = filter;
+        self
+    }
+
+    /// Run at most `jobs` test executable invocations in parallel.
+    pub fn jobs(mut self, jobs: usize) -> RunConfig {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Repeat failed tests up to `repeat` times.
+    pub fn repeat(mut self, repeat: u64) -> RunConfig {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Count tests which failed but passed when repeated as `flaky` in the exit code.
+    pub fn flaky(mut self, flaky: Flaky) -> RunConfig {
+        self.flaky = flaky;
+        self
+    }
+
+    /// Run the tests over and over until the first failure or until the `stress` limits are
+    /// reached. This takes precedence over `repeat`.
+    pub fn stress(mut self, stress: Stress) -> RunConfig {
+        self.stress = Some(stress);
+        self
+    }
+
+    /// Distribute tests over jobs with `schedule`.
+    pub fn schedule(mut self, schedule: Schedule) -> RunConfig {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Set an environment variable for all invocations of test executables.
+    ///
+    /// The environment of the runner process itself is left untouched.
+    pub fn env<K, V>(mut self, key: K, value: V) -> RunConfig
+    where
+        K: Into<OsString>,
+        V: Into<OsString>,
+    {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Run test executables in `dir` instead of the current working directory.
+    pub fn working_dir<P: Into<PathBuf>>(mut self, dir: P) -> RunConfig {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    /// Run every invocation of a test executable in its own fresh working directory.
+    ///
+    /// Like the temporary directory every invocation gets, the working directory is removed if
+    /// the invocation succeeded and kept otherwise. This takes precedence over `working_dir`.
+    pub fn isolate_working_dir(mut self, isolate: bool) -> RunConfig {
+        self.isolate_working_dir = isolate;
+        self
+    }
+
+    /// Run test executables under a wrapper command, e.g., `valgrind --error-exitcode=1`.
+    ///
+    /// The path of the test executable is appended to the wrapper command and its arguments. This
+    /// applies to listing tests as well as to running them. Output of the wrapper is captured like
+    /// the output of the test executable.
+    pub fn wrapper<I, S>(mut self, command: I) -> RunConfig
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.wrapper = command.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Hand every invocation of a test executable its own range of local ports.
+    pub fn ports(mut self, ports: Ports) -> RunConfig {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Never run tests matching the gtest filter `pattern` concurrently with each other.
+    ///
+    /// The tests share the exclusive resource `name`. Multiple patterns can use the same resource.
+    pub fn resource<N, P>(mut self, name: N, pattern: P) -> RunConfig
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        self.resources.push(Resource {
+            name: name.into(),
+            pattern: pattern.into(),
+        });
+        self
+    }
+
+    /// Run tests matching the gtest filter `pattern` serially.
+    ///
+    /// Serial tests are run after all other tests, one test executable at a time, with the serial
+    /// tests of an executable in a single process.
+    pub fn serial<P: Into<String>>(mut self, pattern: P) -> RunConfig {
+        self.serial.push(pattern.into());
+        self
+    }
+
+    /// Override settings of the run for the test executable at `executable`.
+    ///
+    /// Overrides given for the same executable before are kept for the settings not set in
+    /// `overrides`.
+    pub fn overrides<P: Into<PathBuf>>(mut self, executable: P, overrides: Overrides) -> RunConfig {
+        let executable = executable.into();
+        let same = |path: &Path| match (canonicalize(path), canonicalize(&executable)) {
+            (Ok(path), Ok(executable)) => path == executable,
+            _ => path == executable,
+        };
+        match self.overrides.iter_mut().find(|(path, _)| same(path)) {
+            Some((_, existing)) => existing.merge(overrides),
+            None => self.overrides.push((executable, overrides)),
+        }
+        self
+    }
+
+    /// Limit how long tests may run.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> RunConfig {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Record test durations and failed tests in `dir` and use them in later runs.
+    pub fn cache_dir<P: Into<PathBuf>>(mut self, dir: P) -> RunConfig {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Select how tests which failed in the previous run are handled, see `cache_dir`.
+    pub fn rerun(mut self, rerun: Rerun) -> RunConfig {
+        self.rerun = rerun;
+        self
+    }
+
+    /// Overrides for the test executable with the canonicalized path `executable`, if any
+    pub(crate) fn overrides_for(&self, executable: &Path) -> Option<&Overrides> {
+        self.overrides
+            .iter()
+            .find(|(path, _)| canonicalize(path).is_ok_and(|path| path == executable))
+            .map(|(_, overrides)| overrides)
+    }
+
+    /// Filter selecting the tests of a test executable
+    pub(crate) fn filter_for(&self, executable: &Path) -> &Filter {
+        self.overrides_for(executable)
+            .and_then(|overrides| overrides.filter.as_ref())
+            .unwrap_or(&self.filter)
+    }
+
+    /// Value of an environment variable as seen by the test executables
+    pub(crate) fn var(&self, key: &str) -> Option<OsString> {
+        self.env
+            .iter()
+            .rev()
+            .find(|(k, _)| k == OsStr::new(key))
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var_os(key))
+    }
+
+    /// Command invoking a test executable with the configured wrapper, arguments, and environment
+    pub(crate) fn command(&self, executable: &Path) -> Command {
+        let mut command = match self.wrapper.split_first() {
+            Some((wrapper, args)) => {
+                let mut command = Command::new(wrapper);
+                command.args(args).arg(executable);
+                command
+            }
+            None => Command::new(executable),
+        };
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(overrides) = self.overrides_for(executable) {
+            command
+                .args(&overrides.args)
+                .envs(overrides.env.iter().map(|(key, value)| (key, value)));
+        }
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
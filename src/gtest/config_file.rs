@@ -0,0 +1,294 @@
+use {
+    crate::{opt::parse_duration, Error, Filter, Overrides, Result},
+    indexmap::IndexMap,
+    regex::Regex,
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+/// Name of the config file discovered from the current directory upwards
+pub(crate) const FILE_NAME: &str = ".gtest-runner.toml";
+
+/// Defaults for the options of the runner from a config file
+///
+/// Options are named like their command line flags, with `_` instead of `-`, e.g.,
+///
+/// ```toml
+/// jobs = 4
+/// verbosity = 1
+/// timeout = "30s"
+/// serial = ["Device*"]
+///
+/// [env]
+/// DATA_DIR = "testdata"
+///
+/// [resource]
+/// db = "Database*"
+///
+/// [executables."build/slow-test"]
+/// filter = "-*Slow*"
+/// env = { LOG_LEVEL = "debug" }
+/// ```
+///
+/// Relative paths of executables are relative to the directory of the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    jobs: Option<usize>,
+    repeat: Option<u64>,
+    verbosity: Option<u64>,
+    filter: Option<String>,
+    filter_regex: Option<String>,
+    timeout: Option<String>,
+    run_timeout: Option<String>,
+    serial: Option<Vec<String>>,
+    resource: Option<BTreeMap<String, String>>,
+    env: BTreeMap<String, String>,
+    executables: IndexMap<PathBuf, ExecutableConfig>,
+}
+
+/// Overrides for a single test executable in a config file or manifest
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ExecutableConfig {
+    filter: Option<String>,
+    filter_regex: Option<String>,
+    timeout: Option<String>,
+    jobs: Option<usize>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+/// Test executables to run with their overrides
+///
+/// A manifest lists test executables in tables like the overrides of a config file, e.g.,
+///
+/// ```toml
+/// [executables."build/unit-test"]
+///
+/// [executables."build/integration-test"]
+/// timeout = "5m"
+/// jobs = 2
+/// args = ["--data-dir", "testdata"]
+/// env = { LOG_LEVEL = "debug" }
+/// ```
+///
+/// Relative paths of executables are relative to the directory of the manifest. Executables are
+/// run in the order they are listed.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    executables: IndexMap<PathBuf, ExecutableConfig>,
+}
+
+/// Settings of a config file with values parsed like their command line flags
+#[derive(Debug, Default)]
+pub(crate) struct Settings {
+    pub jobs: Option<usize>,
+    pub repeat: Option<u64>,
+    pub verbosity: Option<u64>,
+    pub filter: Option<String>,
+    pub filter_regex: Option<Regex>,
+    pub timeout: Option<Duration>,
+    pub run_timeout: Option<Duration>,
+    pub serial: Option<Vec<String>>,
+    pub resources: Option<Vec<(String, String)>>,
+    pub env: Vec<(String, String)>,
+    pub overrides: Vec<(PathBuf, Overrides)>,
+}
+
+/// Find the config file in `dir` or its closest ancestor containing one
+pub(crate) fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Read the settings from the config file at `path`
+pub(crate) fn load(path: &Path) -> Result<Settings> {
+    let contents = std::fs::read_to_string(path)?;
+    parse(&contents, path.parent().unwrap_or(Path::new(""))).map_err(|message| Error::Config {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+/// Read the test executables and their overrides from the manifest at `path`
+pub(crate) fn load_manifest(path: &Path) -> Result<Vec<(PathBuf, Overrides)>> {
+    let contents = std::fs::read_to_string(path)?;
+    parse_manifest(&contents, path.parent().unwrap_or(Path::new(""))).map_err(|message| {
+        Error::Config {
+            path: path.to_path_buf(),
+            message,
+        }
+    })
+}
+
+/// Parse the contents of a config file in `dir`
+fn parse(contents: &str, dir: &Path) -> Result<Settings, String> {
+    let file: ConfigFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+    Ok(Settings {
+        jobs: file.jobs,
+        repeat: file.repeat,
+        verbosity: file.verbosity,
+        filter: file.filter,
+        filter_regex: parse_regex(file.filter_regex)?,
+        timeout: duration(file.timeout)?,
+        run_timeout: duration(file.run_timeout)?,
+        serial: file.serial,
+        resources: file.resource.map(resources).transpose()?,
+        env: file.env.into_iter().collect(),
+        overrides: overrides(file.executables, dir)?,
+    })
+}
+
+/// Parse the contents of a manifest in `dir`
+fn parse_manifest(contents: &str, dir: &Path) -> Result<Vec<(PathBuf, Overrides)>, String> {
+    let manifest: Manifest = toml::from_str(contents).map_err(|e| e.to_string())?;
+    overrides(manifest.executables, dir)
+}
+
+/// Overrides for the test executables with paths relative to `dir`
+fn overrides(
+    executables: IndexMap<PathBuf, ExecutableConfig>,
+    dir: &Path,
+) -> Result<Vec<(PathBuf, Overrides)>, String> {
+    executables
+        .into_iter()
+        .map(|(path, executable)| {
+            if executable.jobs == Some(0) {
+                return Err(format!("jobs of {} must be at least 1", path.display()));
+            }
+
+            let filter = match (executable.filter, executable.filter_regex) {
+                (None, None) => None,
+                (gtest, regex) => Some(Filter::new(gtest, parse_regex(regex)?)),
+            };
+            let overrides = Overrides {
+                filter,
+                env: executable
+                    .env
+                    .into_iter()
+                    .map(|(key, value)| (key.into(), value.into()))
+                    .collect(),
+                timeout: duration(executable.timeout)?,
+                jobs: executable.jobs,
+                args: executable.args.into_iter().map(Into::into).collect(),
+            };
+            Ok((dir.join(path), overrides))
+        })
+        .collect()
+}
+
+/// Resources as `(name, pattern)` like given with `--resource name=pattern`
+fn resources(resources: BTreeMap<String, String>) -> Result<Vec<(String, String)>, String> {
+    resources
+        .into_iter()
+        .map(|(name, pattern)| {
+            if name.is_empty() || pattern.is_empty() {
+                return Err(format!(
+                    "Expected a resource as name = \"pattern\", got {name:?} = {pattern:?}"
+                ));
+            }
+            Ok((name, pattern))
+        })
+        .collect()
+}
+
+fn parse_regex(regex: Option<String>) -> Result<Option<Regex>, String> {
+    regex
+        .map(|regex| Regex::new(&regex).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+fn duration(duration: Option<String>) -> Result<Option<Duration>, String> {
+    duration.as_deref().map(parse_duration).transpose()
+}
+
+#[test]
+fn test_parse() {
+    let settings = parse(
+        r#"
+            jobs = 4
+            timeout = "1.5s"
+            filter_regex = "^A\\."
+            serial = ["Device*"]
+
+            [env]
+            A = "a"
+
+            [resource]
+            db = "Db*:Sql*"
+
+            [executables."bin/test"]
+            filter = "-*Slow*"
+            env = { B = "b" }
+        "#,
+        Path::new("/project"),
+    )
+    .unwrap();
+
+    assert_eq!(Some(4), settings.jobs);
+    assert_eq!(None, settings.repeat);
+    assert_eq!(Some(Duration::from_millis(1500)), settings.timeout);
+    assert!(settings.filter_regex.unwrap().is_match("A.a"));
+    assert_eq!(vec![("A".to_string(), "a".to_string())], settings.env);
+    assert_eq!(Some(vec!["Device*".to_string()]), settings.serial);
+    assert_eq!(
+        Some(vec![("db".to_string(), "Db*:Sql*".to_string())]),
+        settings.resources
+    );
+
+    let [(path, overrides)] = &settings.overrides[..] else {
+        panic!("Expected one override, got {:?}", settings.overrides);
+    };
+    assert_eq!(Path::new("/project/bin/test"), path);
+    let filter = overrides.filter.as_ref().unwrap();
+    assert!(filter.matches("A.a"));
+    assert!(!filter.matches("A.SlowA"));
+    assert_eq!(vec![("B".into(), "b".into())], overrides.env);
+
+    assert!(parse("jobs = \"many\"", Path::new("")).is_err());
+    assert!(parse("timeout = \"1d\"", Path::new("")).is_err());
+    assert!(parse("unknown = 1", Path::new("")).is_err());
+    assert!(parse("[resource]\ndb = \"\"", Path::new("")).is_err());
+}
+
+#[test]
+fn test_parse_manifest() {
+    let executables = parse_manifest(
+        r#"
+            [executables."bin/a"]
+
+            [executables."/abs/b"]
+            timeout = "5m"
+            jobs = 2
+            args = ["--data", "x"]
+        "#,
+        Path::new("/project"),
+    )
+    .unwrap();
+
+    let paths = executables
+        .iter()
+        .map(|(path, _)| path.as_path())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![Path::new("/project/bin/a"), Path::new("/abs/b")],
+        paths
+    );
+
+    assert!(executables[0].1.filter.is_none());
+    let b = &executables[1].1;
+    assert_eq!(Some(Duration::from_secs(300)), b.timeout);
+    assert_eq!(Some(2), b.jobs);
+    assert_eq!(vec!["--data", "x"], b.args);
+
+    assert!(parse_manifest("", Path::new("")).is_err());
+    assert!(parse_manifest("[executables.a]\njobs = 0", Path::new("")).is_err());
+}
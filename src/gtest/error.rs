@@ -0,0 +1,56 @@
+use std::{io, path::PathBuf, process::ExitStatus};
+
+/// Errors reported by the test runner
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A test executable could not be started.
+    #[error("could not run test executable {}", executable.display())]
+    Spawn {
+        executable: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A test executable failed to list its tests.
+    #[error("listing tests of {} failed ({status}):\n{stdout}{stderr}", executable.display())]
+    Listing {
+        executable: PathBuf,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+
+    /// The test listing of a test executable could not be understood.
+    #[error("unexpected line in test listing of {}: {line:?}", executable.display())]
+    MalformedListing { executable: PathBuf, line: String },
+
+    /// The output of a test executable could not be read.
+    #[error("could not read output of test executable {}", executable.display())]
+    ShardIo {
+        executable: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Rerunning failed tests was requested but no cache directory is available.
+    #[error("rerunning failed tests requires a cache directory")]
+    MissingCacheDir,
+
+    /// No range of ports could be handed out to an invocation of a test executable.
+    #[error("no range of {per_shard} free ports in {first}-{last}")]
+    NoFreePorts {
+        first: u16,
+        last: u16,
+        per_shard: u16,
+    },
+
+    /// A config file could not be understood.
+    #[error("invalid config file {}: {message}", path.display())]
+    Config { path: PathBuf, message: String },
+
+    /// Any other I/O error, e.g., when accessing the cache or writing reports.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,60 +1,172 @@
 use rs_tracing::trace_begin;
 use {
-    crate::{opt::Opt, parse, Event, Test},
-    anyhow::{anyhow, Result},
-    console::style,
+    crate::{
+        cache, config_file, filter, junit,
+        opt::Opt,
+        parse,
+        resources::{self, Allocator},
+        signals, Console, Error, Event, Filter, Format, Jsonl, Ports, Rerun, Result, RunConfig,
+        ShardExit, Status, Stress, Test, Timeouts,
+    },
     core::str,
-    crossbeam::channel::Sender,
+    crossbeam::channel::{self, RecvTimeoutError, Sender},
     rs_tracing::{
         close_trace_file, close_trace_file_internal, open_trace_file, trace_duration_internal,
-        trace_end, trace_scoped, trace_scoped_internal, trace_to_file_internal,
+        trace_end, trace_to_file_internal,
     },
     std::{
+        cmp::min,
         collections::HashSet,
         convert::Into,
-        env,
-        io::{BufRead, BufReader},
-        path::PathBuf,
-        process::{Child, Command, Stdio},
+        env, fs,
+        io::{self, BufRead, BufReader},
+        os::unix::process::ExitStatusExt,
+        path::{Path, PathBuf},
+        process::{Child, Command, ExitStatus},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
         thread,
+        time::{Duration, Instant},
     },
+    tempfile::TempDir,
 };
 
+/// Maximum length in bytes of the `GTEST_FILTER` selecting the tests of a single invocation
+///
+/// Linux limits every single environment string to 128 KiB (`MAX_ARG_STRLEN`), so larger filters
+/// would fail to spawn the test executable.
+const MAX_FILTER_LEN: usize = 64 * 1024;
+
+/// A single invocation of a test executable handed out to a job.
+#[derive(Clone, Debug)]
+pub struct Work {
+    /// The test executable to invoke.
+    pub executable: PathBuf,
+    /// Tests run by this invocation, in the order the test executable runs them.
+    pub tests: Vec<String>,
+    /// Shard index and total number of shards if the tests are selected with gtest's native
+    /// sharding instead of a filter.
+    pub shard: Option<(usize, usize)>,
+}
+
+impl Work {
+    /// Work to run the given tests selected with a filter.
+    pub fn tests(executable: PathBuf, tests: Vec<String>) -> Work {
+        Work {
+            executable,
+            tests,
+            shard: None,
+        }
+    }
+
+    /// Split into work items whose filters are at most `MAX_FILTER_LEN` bytes long
+    ///
+    /// Work selecting tests with gtest's native sharding is returned unchanged.
+    pub(crate) fn split(self) -> Vec<Work> {
+        if self.shard.is_some() {
+            return vec![self];
+        }
+
+        let mut split = vec![];
+        let mut tests = vec![];
+        let mut len = 0;
+        for test in self.tests {
+            // Tests are separated by `:` in the filter.
+            if !tests.is_empty() && len + 1 + test.len() > MAX_FILTER_LEN {
+                split.push(Work::tests(
+                    self.executable.clone(),
+                    std::mem::take(&mut tests),
+                ));
+                len = 0;
+            }
+            len += usize::from(!tests.is_empty()) + test.len();
+            tests.push(test);
+        }
+        if !tests.is_empty() || split.is_empty() {
+            split.push(Work::tests(self.executable, tests));
+        }
+        split
+    }
+
+    /// Work covering the tests which were not yet started, if any.
+    ///
+    /// Its filter is never longer than the filter of this work.
+    fn remaining(&self, started: &HashSet<String>) -> Option<Work> {
+        let tests = self
+            .tests
+            .iter()
+            .filter(|test| !started.contains(*test))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Only hand out remaining work if we made progress so we cannot end up rerunning the same
+        // work forever, e.g., if the executable ran tests we did not expect.
+        (!tests.is_empty() && tests.len() < self.tests.len())
+            .then(|| Work::tests(self.executable.clone(), tests))
+    }
+}
+
+/// List the tests of a test executable
+///
+/// Tests are returned in the order in which the executable would run them.
 pub fn get_tests<P: Into<PathBuf>>(
     test_executable: P,
     include_disabled_tests: bool,
-) -> Result<HashSet<String>> {
-    let result = Command::new(test_executable.into())
-        .env("GTEST_LIST_TESTS", "1")
-        .output()
-        .expect("Failed to execute process");
+) -> Result<Vec<String>> {
+    let executable = test_executable.into();
+    list_tests(
+        Command::new(&executable),
+        &executable,
+        include_disabled_tests,
+    )
+}
+
+/// List the tests of a test executable invoked with `command`
+pub(crate) fn list_tests(
+    mut command: Command,
+    executable: &Path,
+    include_disabled_tests: bool,
+) -> Result<Vec<String>> {
+    let executable = executable.to_path_buf();
+
+    let result = match command.env("GTEST_LIST_TESTS", "1").output() {
+        Ok(result) => result,
+        Err(source) => return Err(Error::Spawn { executable, source }),
+    };
 
     if !result.status.success() {
-        return Err(anyhow!("Failed to run program"));
+        return Err(Error::Listing {
+            executable,
+            status: result.status,
+            stdout: String::from_utf8_lossy(&result.stdout).into(),
+            stderr: String::from_utf8_lossy(&result.stderr).into(),
+        });
     }
 
     let output = String::from_utf8_lossy(&result.stdout);
 
-    let mut tests = HashSet::new();
+    let mut tests = Vec::new();
 
     let mut current_test: Option<&str> = None;
     for line in output.lines() {
         if line.starts_with(' ') {
-            let case = &line
-                .split_whitespace()
-                .next()
-                .ok_or_else(|| anyhow!("Expected test case on line: {}", &line))?;
-
-            let test = match current_test {
-                Some(t) => [t, case].concat(),
-                None => panic!("Couldn't determine test name"),
+            let (Some(case), Some(current_test)) = (line.split_whitespace().next(), current_test)
+            else {
+                return Err(Error::MalformedListing {
+                    executable,
+                    line: line.into(),
+                });
             };
 
+            let test = [current_test, case].concat();
+
             if !include_disabled_tests && test.contains("DISABLED_") {
                 continue;
             }
 
-            tests.insert(test);
+            tests.push(test);
         } else {
             current_test = line.split_whitespace().next();
         }
@@ -63,106 +175,521 @@ pub fn get_tests<P: Into<PathBuf>>(
     Ok(tests)
 }
 
-pub fn cmd<P: Into<PathBuf>>(test_executable: P, job_index: usize, jobs: usize) -> Command {
-    let mut child = Command::new(test_executable.into());
+/// Command running the given work with the environment configured in `config`
+pub fn cmd(config: &RunConfig, work: &Work) -> Command {
+    let mut child = config.command(&work.executable);
 
-    child.env("GTEST_SHARD_INDEX", job_index.to_string());
-    child.env("GTEST_TOTAL_SHARDS", jobs.to_string());
+    match work.shard {
+        Some((index, total)) => {
+            child.env("GTEST_SHARD_INDEX", index.to_string());
+            child.env("GTEST_TOTAL_SHARDS", total.to_string());
+        }
+        None => {
+            child.env("GTEST_FILTER", work.tests.join(":"));
+        }
+    }
     child.env("GTEST_COLOR", "YES");
-    child.stderr(Stdio::null());
-    child.stdout(Stdio::piped());
 
     child
 }
 
-pub fn process_shard(
+/// Progress reported while processing work
+#[derive(Debug)]
+pub enum Message {
+    /// An event of a test.
+    Test(Test),
+    /// An invocation of a test executable exited.
+    ShardExited(ShardExit),
+}
+
+/// Queue `work` with the jobs cap of its test executable and the resources used by its tests
+pub(crate) fn queue(allocator: &Allocator, config: &RunConfig, work: Work) {
+    let jobs = config
+        .overrides_for(&work.executable)
+        .and_then(|overrides| overrides.jobs);
+    let resources = resources::used(&config.resources, &work.tests);
+    allocator.push(work, jobs, resources);
+}
+
+/// Process work items until no more work is available
+///
+/// Work is processed on a separate thread which takes work items from `allocator` and for each
+/// spawns the test executable and forwards parsed test results and the exit of the invocation to
+/// `sender`.
+///
+/// A test running longer than the test timeout in `config` is killed and reported as timed out.
+/// If a test timed out or crashed its invocation, the tests of its work item which never started
+/// are queued again to be run in a new process. Once `deadline` has passed running tests are
+/// killed and no further work is started.
+pub(crate) fn process_work(
     shard: usize,
-    child: Child,
-    sender: Sender<Test>,
-    done: Sender<()>,
-) -> Result<thread::JoinHandle<()>> {
-    // TODO(bbannier): Process stdout as well.
-    let reader = BufReader::new(
-        child
-            .stdout
-            .ok_or_else(|| anyhow!("Child process has not stdout"))?,
-    );
+    sender: Sender<Message>,
+    config: Arc<RunConfig>,
+    allocator: Arc<Allocator>,
+    deadline: Option<Instant>,
+) -> thread::JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
 
-    // The output is processed on a separate thread to not block the main
-    // thread while we wait for output.
-    Ok(thread::spawn(move || {
-        let lines = reader.lines().map(|line| match line {
-            Ok(line) => line,
-            Err(err) => panic!("{}", err),
-        });
+        // Wait for work which can start with the ports and resources it needs.
+        while let Some((current, lease)) = allocator.next()? {
+            if expired() {
+                break;
+            }
+
+            // Every invocation gets fresh temporary directories so tests writing to fixed paths
+            // there do not collide.
+            let scratch = tempfile::Builder::new()
+                .prefix("gtest-runner-")
+                .tempdir_in(
+                    config
+                        .var("TMPDIR")
+                        .map_or_else(env::temp_dir, PathBuf::from),
+                )?;
+            let tmp = scratch.path().join("tmp");
+            fs::create_dir(&tmp)?;
+
+            let timeout = config
+                .overrides_for(&current.executable)
+                .and_then(|overrides| overrides.timeout)
+                .or(config.timeouts.test);
+
+            let mut cmd = cmd(&config, &current);
+            cmd.env("TMPDIR", &tmp).env("TEST_TMPDIR", &tmp);
+            if let Some((base, count)) = lease.ports() {
+                cmd.env("GTEST_RUNNER_PORT_BASE", base.to_string())
+                    .env("GTEST_RUNNER_PORT_COUNT", count.to_string());
+            }
+            if config.isolate_working_dir {
+                let dir = scratch.path().join("work");
+                fs::create_dir(&dir)?;
+                cmd.current_dir(dir);
+            }
+
+            // Capture stdout and stderr through the same pipe so we see their output in the
+            // order it was written.
+            let (reader, writer) = io::pipe()?;
+            cmd.stdout(writer.try_clone()?);
+            cmd.stderr(writer);
+
+            let (mut child, group) = signals::spawn(&mut cmd).map_err(|source| Error::Spawn {
+                executable: current.executable.clone(),
+                source,
+            })?;
+
+            // Close our ends of the pipe so we see the end of the output once the child exits.
+            drop(cmd);
+
+            // When stress testing the first failure is what we are after, so no further tests
+            // are started after it.
+            let stop = config.stress.is_some().then_some(&*allocator);
+
+            let remaining = process_shard(
+                shard, &current, &mut child, group, reader, scratch, &sender, timeout, deadline,
+                stop,
+            )?;
+            drop(lease);
+            if let Some(remaining) = remaining {
+                queue(&allocator, &config, remaining);
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Process the combined stdout and stderr of a single test executable invocation
+///
+/// Once the output ends the invocation is reaped and its exit reported. If the invocation was
+/// killed because it timed out or a test crashed it, this returns the work which was not started.
+///
+/// The `scratch` directory of the invocation is removed if it succeeded, and kept for inspection
+/// otherwise. If the invocation failed, `stop` is stopped from handing out further work.
+#[allow(clippy::too_many_arguments)]
+fn process_shard(
+    shard: usize,
+    work: &Work,
+    child: &mut Child,
+    group: signals::Group,
+    output: io::PipeReader,
+    scratch: TempDir,
+    sender: &Sender<Message>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    stop: Option<&Allocator>,
+) -> Result<Option<Work>> {
+    let mut watchdog =
+        (timeout.is_some() || deadline.is_some()).then(|| Watchdog::new(child.id(), deadline));
+    let mut group = Some(group);
+    let mut expired = false;
 
-        for t in parse::Parser::new(lines) {
-            let mut t = t;
-            t.shard = Some(shard);
+    // Stop processing output on read errors, but remember them so we can report them.
+    let mut read_error = None;
+    let lines = BufReader::new(output)
+        .lines()
+        .map_while(|line| line.map_err(|err| read_error = Some(err)).ok());
 
-            // Update tracing.
-            match &t.event {
-                Event::Starting => {
-                    trace_begin!(&t.testcase);
+    let mut started = HashSet::new();
+    let mut test_start = Instant::now();
+    let mut status = None;
+    let mut failed = false;
+    let mut aborted = false;
+
+    let mut parser = parse::Parser::new(lines);
+    for t in parser.by_ref() {
+        let mut t = t;
+        t.shard = Some(shard);
+        t.executable = Some(work.executable.clone());
+
+        // Update tracing.
+        match &t.event {
+            Event::Starting => {
+                trace_begin!(&t.testcase);
+
+                started.insert(t.testcase.clone());
+                test_start = Instant::now();
+                if let Some(watchdog) = &watchdog {
+                    watchdog
+                        .start_test(timeout.and_then(|timeout| test_start.checked_add(timeout)));
                 }
-                Event::Running => {}
-                Event::Terminal { .. } => {
-                    trace_end!(&t.testcase);
+            }
+            Event::Running => {}
+            Event::Terminal { .. } => {
+                trace_end!(&t.testcase);
+
+                if let Some(watchdog) = &watchdog {
+                    watchdog.start_test(None);
                 }
-            };
+            }
+        };
+
+        if let Event::Terminal { duration, .. } = &mut t.event {
+            // Fall back to the wall-clock time if gtest did not report a duration.
+            duration.get_or_insert_with(|| test_start.elapsed());
+        }
 
-            sender.send(t).unwrap();
+        if let Event::Terminal {
+            status: test_status,
+            log,
+            ..
+        } = &mut t.event
+        {
+            // A test is only aborted if the output ended while it was running, so the
+            // invocation has exited or is about to.
+            if *test_status == Status::Aborted {
+                // Nothing must signal the process group once we reaped its leader.
+                if let Some(watchdog) = watchdog.take() {
+                    expired = watchdog.disarm();
+                }
+                drop(group.take());
+
+                if expired {
+                    // If we killed the test it shows up as aborted.
+                    *test_status = Status::Timeout;
+                    log.push(format!(
+                        "[  TIMEOUT ] {} ({} ms)",
+                        t.testcase,
+                        test_start.elapsed().as_millis()
+                    ));
+                } else {
+                    let exit = wait(child, work)?;
+                    log.push(format!("[  ABORTED ] {} ({})", t.testcase, describe(exit)));
+                    status = Some(exit);
+                    aborted = true;
+                }
+            }
+
+            if test_status.is_failed() {
+                failed = true;
+                log.push(kept(&scratch));
+                if let Some(allocator) = stop {
+                    allocator.stop();
+                }
+            }
         }
 
-        // Signal that we are done processing this shard.
-        done.send(()).unwrap();
-    }))
+        sender.send(Message::Test(t)).unwrap();
+    }
+
+    let output = parser.output().to_vec();
+    drop(parser);
+
+    if let Some(source) = read_error {
+        return Err(Error::ShardIo {
+            executable: work.executable.clone(),
+            source,
+        });
+    }
+
+    if let Some(watchdog) = watchdog.take() {
+        expired = watchdog.disarm();
+    }
+    drop(group);
+    let status = match status {
+        Some(status) => status,
+        None => wait(child, work)?,
+    };
+
+    // gtest exits unsuccessfully if tests failed, and is never killed by a signal unless a test
+    // crashed. Anything else happened outside of any test.
+    let error = if expired || aborted || (failed && status.signal().is_none()) {
+        None
+    } else {
+        (!status.success()).then(|| describe(status))
+    };
+
+    let mut log = output;
+    if failed || error.is_some() {
+        if error.is_some() {
+            log.push(kept(&scratch));
+            if let Some(allocator) = stop {
+                allocator.stop();
+            }
+        }
+        let _ = scratch.keep();
+    }
+
+    sender
+        .send(Message::ShardExited(ShardExit {
+            shard,
+            executable: work.executable.clone(),
+            status,
+            error,
+            log,
+        }))
+        .unwrap();
+
+    if expired || aborted {
+        return Ok(work.remaining(&started));
+    }
+
+    Ok(None)
+}
+
+/// Log line pointing to the kept `scratch` directory of a failed invocation
+fn kept(scratch: &TempDir) -> String {
+    format!("Temporary files kept in {}", scratch.path().display())
+}
+
+/// Reap an invocation of a test executable
+fn wait(child: &mut Child, work: &Work) -> Result<ExitStatus> {
+    child.wait().map_err(|source| Error::ShardIo {
+        executable: work.executable.clone(),
+        source,
+    })
+}
+
+/// Human-readable description of how a process exited, e.g., "killed by SIGSEGV"
+pub(crate) fn describe(status: ExitStatus) -> String {
+    if let Some(signal) = status.signal() {
+        return match signal_name(signal) {
+            Some(name) => format!("killed by {name}"),
+            None => format!("killed by signal {signal}"),
+        };
+    }
+
+    match status.code() {
+        Some(code) => format!("exited with code {code}"),
+        None => status.to_string(),
+    }
+}
+
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGILL => "SIGILL",
+        libc::SIGINT => "SIGINT",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGSYS => "SIGSYS",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGTRAP => "SIGTRAP",
+        _ => return None,
+    })
+}
+
+/// Kills a process group once the current test or the whole run is past its deadline.
+struct Watchdog {
+    test_deadline: Sender<Option<Instant>>,
+    expired: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Watchdog {
+    fn new(pgid: u32, run_deadline: Option<Instant>) -> Watchdog {
+        let (test_deadline, receiver) = channel::unbounded::<Option<Instant>>();
+        let expired = Arc::new(AtomicBool::new(false));
+
+        let killed = expired.clone();
+        let thread = thread::spawn(move || {
+            let mut deadline = run_deadline;
+            loop {
+                let update = match deadline {
+                    Some(deadline) => receiver.recv_deadline(deadline),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+
+                match update {
+                    Ok(test_deadline) => {
+                        deadline = match (test_deadline, run_deadline) {
+                            (Some(a), Some(b)) => Some(min(a, b)),
+                            (a, b) => a.or(b),
+                        };
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        killed.store(true, Ordering::SeqCst);
+
+                        // SAFETY: `kill` has no memory safety requirements. The process group
+                        // cannot have been reused since its leader is only reaped once this
+                        // thread exited, see `Watchdog::disarm`.
+                        unsafe {
+                            libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+                        }
+                        return;
+                    }
+                    // The watched process finished.
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Watchdog {
+            test_deadline,
+            expired,
+            thread,
+        }
+    }
+
+    /// Set the deadline for the currently running test, if any.
+    fn start_test(&self, deadline: Option<Instant>) {
+        // The watchdog thread only exits after it killed the process.
+        let _ = self.test_deadline.send(deadline);
+    }
+
+    /// Stop watching the process group and return whether it was killed
+    ///
+    /// This waits for the watchdog thread to exit, so the process group is never killed after
+    /// this returned. Call this before reaping the leader of the process group.
+    fn disarm(self) -> bool {
+        drop(self.test_deadline);
+        let _ = self.thread.join();
+        self.expired.load(Ordering::SeqCst)
+    }
 }
 
 pub fn exec(opt: &Opt) -> Result<i32> {
-    let ret = if let Some(test_executables) = &opt.mode.test_executables {
+    let manifest = match &opt.mode.manifest {
+        Some(path) => Some(config_file::load_manifest(path)?),
+        None => None,
+    };
+    let test_executables: Option<Vec<PathBuf>> = match (&opt.mode.test_executables, &manifest) {
+        (Some(executables), _) => Some(executables.iter().map(PathBuf::from).collect()),
+        (None, Some(manifest)) => Some(manifest.iter().map(|(path, _)| path.clone()).collect()),
+        (None, None) => None,
+    };
+
+    let ret = if let Some(test_executables) = test_executables {
         if opt.trace {
-            open_trace_file!(".").unwrap();
+            open_trace_file!(".")?;
         }
 
-        let available_parallelism = std::thread::available_parallelism()?.into();
+        // Tests run in their own process groups, so they only see signals we forward.
+        signals::forward()?;
 
-        let mut ret_vec = Vec::new();
-        for exe in test_executables {
-            if test_executables.len() > 1 && opt.verbosity > 0 {
-                println!("{}", style(format!("Running {exe}")).bold());
-            }
-            trace_scoped!(exe);
-            ret_vec.push(crate::run(
-                exe,
-                None,
-                opt.jobs.unwrap_or(available_parallelism),
-                opt.verbosity,
-                opt.repeat,
-            )?);
+        let mut config = RunConfig::new(test_executables)
+            .filter(Filter::new(opt.filter.clone(), opt.filter_regex.clone()))
+            .repeat(opt.repeat)
+            .flaky(opt.flaky)
+            .schedule(opt.schedule)
+            .timeouts(Timeouts {
+                test: opt.timeout,
+                run: opt.run_timeout,
+            })
+            .rerun(if opt.only_failed {
+                Rerun::OnlyFailed
+            } else if opt.failed_first {
+                Rerun::FailedFirst
+            } else {
+                Rerun::All
+            });
+        if let Some(jobs) = opt.jobs {
+            config = config.jobs(jobs);
+        }
+        if opt.isolate_working_dir {
+            config = config.isolate_working_dir(true);
+        }
+        if let Some(per_shard) = opt.ports_per_shard {
+            config = config.ports(Ports {
+                range: opt.port_range.clone().unwrap_or(20000..=29999),
+                per_shard,
+            });
+        }
+        for (name, pattern) in &opt.resources {
+            config = config.resource(name, pattern);
+        }
+        for pattern in &opt.serial {
+            config = config.serial(pattern);
         }
+        for (key, value) in &opt.env {
+            config = config.env(key, value);
+        }
+        for (executable, overrides) in opt.overrides.iter().chain(manifest.iter().flatten()) {
+            config = config.overrides(executable, overrides.clone());
+        }
+        if let Some(wrapper) = &opt.wrapper {
+            config = config.wrapper(wrapper.split_whitespace());
+        }
+        if opt.stress.is_some() || opt.stress_duration.is_some() {
+            config = config.stress(Stress {
+                iterations: opt.stress,
+                duration: opt.stress_duration,
+            });
+        }
+        if let Some(dir) = opt.cache_dir.clone().or_else(cache::default_dir) {
+            config = config.cache_dir(dir);
+        }
+
+        let summary = match opt.format {
+            Format::Console => crate::run(
+                &config,
+                &mut [&mut Console::new(opt.verbosity, opt.slowest)],
+            )?,
+            Format::Jsonl => crate::run(&config, &mut [&mut Jsonl])?,
+        };
 
         close_trace_file!();
 
-        i32::try_from(ret_vec.iter().sum::<usize>()).map_err(|e| anyhow!(e.to_string()))
+        if let Some(path) = &opt.junit {
+            junit::write(path, &summary.results)?;
+        }
+
+        Ok(summary.exit_code())
     } else {
         Ok(0)
     };
 
     if let Some(true) = opt.mode.sample_data {
-        sample_data();
-        return Ok(0);
+        return Ok(sample_data());
     }
 
     ret
 }
 
-fn sample_data() {
+fn sample_data() -> i32 {
     fn parse_arg(args: &[String], flag: &str, env: &str) -> Option<String> {
         args.iter()
             .find_map(|a| {
                 if a.starts_with(&format!("--{flag}")) {
-                    a.split('=').next().or(Some("")).map(|x| x.to_string())
+                    a.split_once('=')
+                        .map(|(_, value)| value)
+                        .or(Some(""))
+                        .map(|x| x.to_string())
                 } else {
                     None
                 }
@@ -178,44 +705,171 @@ fn sample_data() {
         .and_then(|x| x.parse::<usize>().ok())
         .unwrap_or(1);
     let gtest_list_tests = parse_arg(&args, "gtest_list_tests", "GTEST_LIST_TESTS");
+    let gtest_filter = parse_arg(&args, "gtest_filter", "GTEST_FILTER");
 
-    if gtest_list_tests.is_some() {
-        println!(
-            r#"NOPE.
-  NOPE0
-  NOPE1"#
+    // Tests which should never finish.
+    let hanging_tests = env::var("GTEST_RUNNER_SAMPLE_DATA_HANG").unwrap_or_default();
+
+    // Tests which should be skipped.
+    let skipped_tests = env::var("GTEST_RUNNER_SAMPLE_DATA_SKIP").unwrap_or_default();
+
+    // Tests which should fail.
+    let failing_tests = env::var("GTEST_RUNNER_SAMPLE_DATA_FAIL").unwrap_or_default();
+
+    // Tests which should fail on their n-th run, by default the first. Runs are counted in the
+    // state directory.
+    let flaky_tests = env::var("GTEST_RUNNER_SAMPLE_DATA_FLAKY").unwrap_or_default();
+    let flaky_run = env::var("GTEST_RUNNER_SAMPLE_DATA_FLAKY_RUN")
+        .ok()
+        .and_then(|run| run.parse::<u64>().ok())
+        .unwrap_or(1);
+    let state_dir = env::var_os("GTEST_RUNNER_SAMPLE_DATA_STATE").map(PathBuf::from);
+
+    // Tests which should crash the executable.
+    let crashing_tests = env::var("GTEST_RUNNER_SAMPLE_DATA_CRASH").unwrap_or_default();
+
+    // Exit code after all tests ran, e.g., to simulate a failing global tear-down.
+    let exit_code = env::var("GTEST_RUNNER_SAMPLE_DATA_EXIT")
+        .ok()
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+
+    // Like gtest we reject invalid sharding configurations.
+    if gtest_shard_index >= gtest_total_shards {
+        eprintln!(
+            "Invalid environment variables: we require 0 <= GTEST_SHARD_INDEX < GTEST_TOTAL_SHARDS, \
+             but you have GTEST_SHARD_INDEX={gtest_shard_index}, GTEST_TOTAL_SHARDS={gtest_total_shards}"
         );
-        return;
+        return 1;
     }
 
-    assert!(
-        gtest_shard_index < gtest_total_shards,
-        "Shard index ({gtest_shard_index}) is too large for number of shards ({gtest_total_shards})"
-    );
+    let tests = ["NOPE.NOPE0", "NOPE.NOPE1"]
+        .into_iter()
+        .filter(|test| {
+            gtest_filter
+                .as_ref()
+                .is_none_or(|filter| filter::matches_gtest_filter(filter, test))
+        })
+        .collect::<Vec<_>>();
 
-    match gtest_total_shards {
-        1 => {
-            println!(
-                r#"[==========] Running 2 tests from 1 test case.
-[----------] Global test environment set-up.
-[----------] 2 tests from NOPE
-[ RUN      ] NOPE.NOPE0
-[       OK ] NOPE.NOPE0 (0 ms)
-[ RUN      ] NOPE.NOPE1
-[       OK ] NOPE.NOPE1 (0 ms)"#
-            );
-        }
-        2 => {
-            println!(
-                r#"[==========] Running 1 tests from 1 test case.
+    if gtest_list_tests.is_some() {
+        println!("NOPE.");
+        for test in &tests {
+            println!("  {}", &test[5..]);
+        }
+        return 0;
+    }
+
+    // Like gtest we assign tests to shards round-robin.
+    let tests = tests
+        .into_iter()
+        .skip(gtest_shard_index)
+        .step_by(gtest_total_shards)
+        .collect::<Vec<_>>();
+
+    println!(
+        r#"[==========] Running {} tests from 1 test case.
 [----------] Global test environment set-up.
-[----------] 1 tests from NOPE
-[ RUN      ] NOPE.NOPE{gtest_shard_index}
-[       OK ] NOPE.NOPE{gtest_shard_index} (0 ms)"#
-            );
+[----------] {} tests from NOPE"#,
+        tests.len(),
+        tests.len()
+    );
+    // Tests leave a file in their temporary directory, if any.
+    let test_tmpdir = env::var_os("TEST_TMPDIR").map(PathBuf::from);
+
+    // Tests report the ports handed to them, if any.
+    let port_base = env::var("GTEST_RUNNER_PORT_BASE").ok();
+
+    // Tests report extra arguments passed to the executable, if any.
+    let arguments = args[1..].join(" ");
+
+    for test in tests {
+        println!("[ RUN      ] {test}");
+        eprintln!("Output of {test} on stderr");
+
+        if let Some(dir) = &test_tmpdir {
+            let _ = std::fs::write(dir.join(test), test);
+        }
+
+        if let Some(port) = &port_base {
+            println!("Using ports from {port}");
+        }
+
+        if !arguments.is_empty() {
+            println!("Arguments {arguments}");
         }
-        n => {
-            panic!("Request {n} shards, but only up to 2 shards are supported");
+
+        if hanging_tests.split(':').any(|t| t == test) {
+            loop {
+                thread::sleep(Duration::from_secs(3600));
+            }
+        }
+
+        if skipped_tests.split(':').any(|t| t == test) {
+            println!("sample.cc:42: Skipped");
+            println!("[  SKIPPED ] {test} (0 ms)");
+            continue;
+        }
+
+        if failing_tests.split(':').any(|t| t == test) {
+            println!("sample.cc:42: Failure");
+            println!("[  FAILED  ] {test} (0 ms)");
+            continue;
+        }
+
+        if let (true, Some(dir)) = (flaky_tests.split(':').any(|t| t == test), &state_dir) {
+            let runs = dir.join(test);
+            let run = std::fs::read_to_string(&runs)
+                .ok()
+                .and_then(|runs| runs.parse::<u64>().ok())
+                .unwrap_or(0)
+                + 1;
+            let _ = std::fs::write(&runs, run.to_string());
+            if run == flaky_run {
+                println!("sample.cc:42: Failure in run {run}");
+                println!("[  FAILED  ] {test} (0 ms)");
+                continue;
+            }
+        }
+
+        if crashing_tests.split(':').any(|t| t == test) {
+            std::process::abort();
         }
+
+        println!("[       OK ] {test} (0 ms)");
+    }
+
+    if exit_code != 0 {
+        println!("[----------] Global test environment tear-down");
+        println!("sample.cc:7: Failure");
+    }
+
+    exit_code
+}
+
+#[test]
+fn test_split() {
+    let tests = (0..20_000)
+        .map(|i| format!("Suite.Test{i:010}"))
+        .collect::<Vec<_>>();
+    let split = Work::tests("/exe".into(), tests.clone()).split();
+
+    assert!(split.len() > 1);
+    assert!(split
+        .iter()
+        .all(|work| work.tests.join(":").len() <= MAX_FILTER_LEN));
+    assert_eq!(
+        tests,
+        split
+            .into_iter()
+            .flat_map(|work| work.tests)
+            .collect::<Vec<_>>()
+    );
+
+    let work = Work {
+        executable: "/exe".into(),
+        tests: tests.clone(),
+        shard: Some((0, 2)),
     };
+    assert_eq!(1, work.split().len());
 }
@@ -0,0 +1,86 @@
+use regex::Regex;
+
+/// Selection of the tests to run
+///
+/// Tests can be selected with a filter in gtest syntax and a regular expression over test names.
+/// A test is selected if it matches both.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    gtest: Option<String>,
+    regex: Option<Regex>,
+}
+
+impl Filter {
+    pub fn new(gtest: Option<String>, regex: Option<Regex>) -> Filter {
+        Filter { gtest, regex }
+    }
+
+    /// Check whether a test is selected by this filter
+    pub fn matches(&self, test: &str) -> bool {
+        self.gtest
+            .as_ref()
+            .is_none_or(|filter| matches_gtest_filter(filter, test))
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(test))
+    }
+
+    /// Check whether this filter selects all tests
+    pub fn is_empty(&self) -> bool {
+        self.gtest.is_none() && self.regex.is_none()
+    }
+}
+
+/// Check whether a test name matches a gtest filter
+///
+/// A filter consists of `:`-separated positive patterns optionally followed by `-` and
+/// `:`-separated negative patterns. Patterns can contain the wildcards `*` and `?`.
+pub(crate) fn matches_gtest_filter(filter: &str, test: &str) -> bool {
+    fn matches_pattern(pattern: &[u8], test: &[u8]) -> bool {
+        match (pattern.first(), test.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                matches_pattern(&pattern[1..], test)
+                    || (!test.is_empty() && matches_pattern(pattern, &test[1..]))
+            }
+            (Some(b'?'), Some(_)) => matches_pattern(&pattern[1..], &test[1..]),
+            (Some(p), Some(t)) if p == t => matches_pattern(&pattern[1..], &test[1..]),
+            _ => false,
+        }
+    }
+
+    let matches_any = |patterns: &str| {
+        patterns
+            .split(':')
+            .any(|pattern| matches_pattern(pattern.as_bytes(), test.as_bytes()))
+    };
+
+    let (positive, negative) = filter.split_once('-').unwrap_or((filter, ""));
+    let positive = if positive.is_empty() { "*" } else { positive };
+
+    matches_any(positive) && !matches_any(negative)
+}
+
+#[test]
+fn test_filter() {
+    let filter = |gtest: Option<&str>, regex: Option<&str>| {
+        Filter::new(
+            gtest.map(Into::into),
+            regex.map(|regex| Regex::new(regex).unwrap()),
+        )
+    };
+
+    assert!(filter(None, None).matches("A.B"));
+
+    assert!(filter(Some("A.*"), None).matches("A.B"));
+    assert!(!filter(Some("A.*"), None).matches("B.B"));
+    assert!(filter(Some("A.?:B.*"), None).matches("B.Long"));
+    assert!(!filter(Some("*-A.B"), None).matches("A.B"));
+    assert!(filter(Some("-A.B"), None).matches("A.C"));
+
+    assert!(filter(None, Some("^A\\.B[0-9]$")).matches("A.B1"));
+    assert!(!filter(None, Some("^A\\.B[0-9]$")).matches("A.B10"));
+
+    // Tests need to match both filters.
+    assert!(filter(Some("A.*"), Some("1$")).matches("A.B1"));
+    assert!(!filter(Some("A.*"), Some("1$")).matches("A.B2"));
+    assert!(!filter(Some("B.*"), Some("1$")).matches("A.B1"));
+}
@@ -0,0 +1,125 @@
+use {
+    crate::{Event, Reporter, ShardExit, Status, Test},
+    serde_json::json,
+};
+
+/// Reporter writing test events to stdout as JSON lines
+pub struct Jsonl;
+
+impl Reporter for Jsonl {
+    fn test_started(&mut self, test: &Test) {
+        if let Some(line) = event(test) {
+            println!("{line}");
+        }
+    }
+
+    fn test_finished(&mut self, test: &Test) {
+        if let Some(line) = event(test) {
+            println!("{line}");
+        }
+    }
+
+    fn shard_exited(&mut self, exit: &ShardExit) {
+        if let Some(line) = shard_error(exit) {
+            println!("{line}");
+        }
+    }
+}
+
+/// Serialize a test event as a single line of JSON
+///
+/// Only the start and the end of tests are reported, output of running tests is contained in the
+/// log of the terminal event.
+pub fn event(test: &Test) -> Option<String> {
+    let mut object = json!({
+        "test": test.testcase,
+        "shard": test.shard,
+        "executable": test
+            .executable
+            .as_ref()
+            .map(|executable| executable.to_string_lossy()),
+    });
+
+    match &test.event {
+        Event::Starting => {
+            object["event"] = "starting".into();
+        }
+        Event::Running => return None,
+        Event::Terminal {
+            status,
+            log,
+            duration,
+        } => {
+            object["event"] = "terminal".into();
+            object["status"] = status_name(status).into();
+            object["duration_ms"] = duration
+                .map(|duration| u64::try_from(duration.as_millis()).unwrap_or(u64::MAX))
+                .into();
+            object["log"] = log.clone().into();
+        }
+    }
+
+    Some(object.to_string())
+}
+
+/// Serialize a failure of an invocation outside of any test as a single line of JSON
+pub fn shard_error(exit: &ShardExit) -> Option<String> {
+    let error = exit.error.as_ref()?;
+
+    Some(
+        json!({
+            "event": "shard_error",
+            "shard": exit.shard,
+            "executable": exit.executable.to_string_lossy(),
+            "error": error,
+            "log": exit.log,
+        })
+        .to_string(),
+    )
+}
+
+fn status_name(status: &Status) -> &'static str {
+    match status {
+        Status::Ok => "ok",
+        Status::Failed => "failed",
+        Status::Aborted => "aborted",
+        Status::Timeout => "timeout",
+        Status::Skipped => "skipped",
+    }
+}
+
+#[test]
+fn test_event() {
+    use std::time::Duration;
+
+    let test = |event| Test {
+        testcase: "NOPE.NOPE0".into(),
+        shard: Some(1),
+        executable: Some("/test-exe".into()),
+        event,
+    };
+
+    assert_eq!(
+        Some(
+            r#"{"event":"starting","executable":"/test-exe","shard":1,"test":"NOPE.NOPE0"}"#.into()
+        ),
+        event(&test(Event::Starting))
+    );
+
+    assert_eq!(None, event(&test(Event::Running)));
+
+    assert_eq!(
+        Some(
+            r#"{"duration_ms":12,"event":"terminal","executable":"/test-exe","log":["[ RUN      ] NOPE.NOPE0","[  FAILED  ] NOPE.NOPE0 (12 ms)"],"shard":1,"status":"failed","test":"NOPE.NOPE0"}"#
+                .into()
+        ),
+        event(&test(Event::Terminal {
+            status: Status::Failed,
+            log: vec![
+                "[ RUN      ] NOPE.NOPE0".into(),
+                "[  FAILED  ] NOPE.NOPE0 (12 ms)".into()
+            ],
+            duration: Some(Duration::from_millis(12)),
+        }))
+    );
+}
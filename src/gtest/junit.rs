@@ -0,0 +1,221 @@
+use {
+    crate::{Event, Result, Status, Test},
+    std::{
+        fmt::Write as _,
+        fs,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+/// Write a JUnit XML report
+///
+/// Every test executable is reported as a `testsuite` containing a `testcase` for every
+/// terminal test result. Failed and timed out tests are reported as failures, aborted
+/// tests as errors, and skipped tests as skipped.
+pub fn write<P: AsRef<Path>>(path: P, results: &[Test]) -> Result<()> {
+    fs::write(path, report(results))?;
+    Ok(())
+}
+
+fn report(results: &[Test]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let count = |tests: &[Test], f: fn(&Status) -> bool| {
+        tests
+            .iter()
+            .filter(|test| matches!(&test.event, Event::Terminal { status, .. } if f(status)))
+            .count()
+    };
+    let time = |tests: &[Test]| {
+        tests
+            .iter()
+            .filter_map(|test| match &test.event {
+                Event::Terminal { duration, .. } => *duration,
+                _ => None,
+            })
+            .sum::<Duration>()
+    };
+
+    let _ = writeln!(
+        xml,
+        r#"<testsuites tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+        results.len(),
+        count(results, is_failure),
+        count(results, is_error),
+        count(results, is_skipped),
+        time(results).as_secs_f64(),
+    );
+
+    // Group results by test executable, in the order in which executables first appear.
+    let mut executables: Vec<(&Option<PathBuf>, Vec<Test>)> = vec![];
+    for test in results {
+        match executables
+            .iter_mut()
+            .find(|(executable, _)| *executable == &test.executable)
+        {
+            Some((_, tests)) => tests.push(test.clone()),
+            None => executables.push((&test.executable, vec![test.clone()])),
+        }
+    }
+
+    for (executable, tests) in &executables {
+        let _ = writeln!(
+            xml,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+            escape(
+                &executable
+                    .as_ref()
+                    .map(|executable| executable.display().to_string())
+                    .unwrap_or_default()
+            ),
+            tests.len(),
+            count(tests, is_failure),
+            count(tests, is_error),
+            count(tests, is_skipped),
+            time(tests).as_secs_f64(),
+        );
+
+        for test in tests {
+            let Event::Terminal {
+                status,
+                log,
+                duration,
+            } = &test.event
+            else {
+                continue;
+            };
+
+            let (classname, name) = test
+                .testcase
+                .split_once('.')
+                .unwrap_or(("", &test.testcase));
+
+            let _ = write!(
+                xml,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                escape(name),
+                escape(classname),
+                duration.unwrap_or_default().as_secs_f64(),
+            );
+
+            let element = match status {
+                Status::Ok => {
+                    xml.push_str("/>\n");
+                    continue;
+                }
+                Status::Skipped => {
+                    xml.push_str(">\n      <skipped/>\n    </testcase>\n");
+                    continue;
+                }
+                Status::Failed | Status::Timeout => "failure",
+                Status::Aborted => "error",
+            };
+            let message = match status {
+                Status::Timeout => "timed out",
+                Status::Aborted => "aborted",
+                _ => "failed",
+            };
+
+            let _ = writeln!(
+                xml,
+                r#">
+      <{element} message="{message}">{}</{element}>
+    </testcase>"#,
+                escape(&log.join("\n")),
+            );
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+
+    xml
+}
+
+fn is_failure(status: &Status) -> bool {
+    matches!(status, Status::Failed | Status::Timeout)
+}
+
+fn is_error(status: &Status) -> bool {
+    matches!(status, Status::Aborted)
+}
+
+fn is_skipped(status: &Status) -> bool {
+    matches!(status, Status::Skipped)
+}
+
+/// Escape text for use in XML attributes and text nodes
+///
+/// Characters which cannot appear in XML documents at all are dropped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[test]
+fn test_report() {
+    let test = |testcase: &str, status, log: &[&str], duration| Test {
+        testcase: testcase.into(),
+        shard: None,
+        executable: Some("/test-exe".into()),
+        event: Event::Terminal {
+            status,
+            log: log.iter().map(|line| line.to_string()).collect(),
+            duration,
+        },
+    };
+
+    let results = [
+        test(
+            "NOPE.NOPE0",
+            Status::Ok,
+            &[],
+            Some(Duration::from_millis(1500)),
+        ),
+        test(
+            "NOPE.NOPE1",
+            Status::Failed,
+            &["[ RUN      ] NOPE.NOPE1", "Expected: a < b"],
+            Some(Duration::ZERO),
+        ),
+        test("NOPE.NOPE2", Status::Aborted, &["\x07boom"], None),
+        test("NOPE.NOPE3", Status::Skipped, &[], None),
+    ];
+
+    assert_eq!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="4" failures="1" errors="1" skipped="1" time="1.500">
+  <testsuite name="/test-exe" tests="4" failures="1" errors="1" skipped="1" time="1.500">
+    <testcase name="NOPE0" classname="NOPE" time="1.500"/>
+    <testcase name="NOPE1" classname="NOPE" time="0.000">
+      <failure message="failed">[ RUN      ] NOPE.NOPE1
+Expected: a &lt; b</failure>
+    </testcase>
+    <testcase name="NOPE2" classname="NOPE" time="0.000">
+      <error message="aborted">boom</error>
+    </testcase>
+    <testcase name="NOPE3" classname="NOPE" time="0.000">
+      <skipped/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#,
+        report(&results)
+    );
+}
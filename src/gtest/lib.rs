@@ -1,32 +1,132 @@
 use {
-    anyhow::Result,
-    console::style,
     crossbeam::channel,
-    indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle},
     rs_tracing::{trace_scoped, trace_scoped_internal},
-    std::{cmp::min, env, fs::canonicalize, path::PathBuf, sync::Arc, thread},
+    std::{
+        cmp::{max, min, Reverse},
+        collections::{BTreeSet, BinaryHeap, HashSet},
+        fs::canonicalize,
+        path::PathBuf,
+        process::ExitStatus,
+        sync::Arc,
+    },
 };
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tracing::info_span;
 
+mod cache;
+mod config;
+mod config_file;
+mod error;
 pub mod exec;
+mod filter;
+mod jsonl;
+mod junit;
 pub mod opt;
 mod parse;
+mod report;
+mod resources;
+mod signals;
+mod summary;
+
+pub use {
+    config::{Overrides, RunConfig},
+    error::{Error, Result},
+    filter::Filter,
+    jsonl::Jsonl,
+    report::{Console, Reporter},
+    resources::{Ports, Resource},
+    summary::{Attempt, Outcome, RunSummary, TestId, TestSummary},
+};
+
+/// Strategy for distributing tests over parallel jobs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Schedule {
+    /// Every job runs a fixed shard of the tests using gtest's native sharding.
+    #[default]
+    Shard,
+    /// Jobs pull small batches of tests from a shared queue whenever they become idle.
+    Queue,
+    /// Every job runs a group of tests with about the same total duration, based on the durations
+    /// recorded in previous runs. Tests are assigned to jobs longest first.
+    Balanced,
+}
+
+/// Format of the output on stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Progress bars and a summary for humans.
+    #[default]
+    Console,
+    /// One JSON object per line for every test start and result, as they happen.
+    Jsonl,
+}
+
+/// Handling of tests which failed in the previous run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rerun {
+    /// Run all tests without regard to previous failures.
+    #[default]
+    All,
+    /// Run tests which failed in the previous run before all other tests.
+    FailedFirst,
+    /// Only run tests which failed in the previous run.
+    OnlyFailed,
+}
+
+/// Limits of stress testing
+///
+/// When stress testing, the selected tests are run over and over until the first test fails, or
+/// until one of the limits is reached.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stress {
+    /// Maximum number of iterations.
+    pub iterations: Option<u64>,
+    /// Maximum duration after which no further iterations are started.
+    pub duration: Option<Duration>,
+}
+
+/// How flaky tests count towards the exit code
+///
+/// A test is flaky if it failed but then passed when repeated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Flaky {
+    /// Flaky tests count as passed.
+    #[default]
+    Pass,
+    /// Flaky tests count as failed.
+    Fail,
+}
+
+/// Number of batches per job when tests are scheduled with `Schedule::Queue`.
+///
+/// Smaller batches balance the load across jobs better, but require more process spawns.
+const QUEUE_BATCHES_PER_JOB: usize = 4;
+
+/// Limits on how long tests may run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// Maximum duration of a single test.
+    pub test: Option<Duration>,
+    /// Maximum duration of the whole run.
+    pub run: Option<Duration>,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Status {
     Ok,
     Failed,
     Aborted,
+    Timeout,
+    Skipped,
 }
 
 impl Status {
     pub fn is_failed(&self) -> bool {
         match self {
-            Status::Failed | Status::Aborted => true,
-            Status::Ok => false,
+            Status::Failed | Status::Aborted | Status::Timeout => true,
+            Status::Ok | Status::Skipped => false,
         }
     }
 }
@@ -35,239 +135,517 @@ impl Status {
 pub enum Event {
     Starting,
     Running,
-    Terminal { status: Status, log: Vec<String> },
+    Terminal {
+        status: Status,
+        log: Vec<String>,
+        duration: Option<Duration>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct Test {
-    event: Event,
-    testcase: String,
-    shard: Option<usize>,
+    pub event: Event,
+    pub testcase: String,
+    pub shard: Option<usize>,
+    pub executable: Option<PathBuf>,
+}
+
+/// Exit of an invocation of a test executable
+#[derive(Debug, Clone)]
+pub struct ShardExit {
+    /// Index of the job which ran the invocation, see `Test::shard`.
+    pub shard: usize,
+    pub executable: PathBuf,
+    pub status: ExitStatus,
+    /// Description of a failure outside of any test, e.g., in global set-up or tear-down, or
+    /// during static initialization. Failures of tests are reported on the tests instead.
+    pub error: Option<String>,
+    /// Output of the invocation which was not part of any test.
+    pub log: Vec<String>,
 }
 
 struct ShardStats {
     num_passed: usize,
+    num_skipped: usize,
     failed_tests: Vec<Test>,
+    results: Vec<Test>,
 }
 
 impl ShardStats {
+    fn new(results: Vec<Test>) -> ShardStats {
+        let mut stats = ShardStats {
+            num_passed: 0,
+            num_skipped: 0,
+            failed_tests: vec![],
+            results: vec![],
+        };
+
+        for test in results {
+            if let Event::Terminal { status, .. } = &test.event {
+                if status.is_failed() {
+                    stats.failed_tests.push(test.clone());
+                } else if *status == Status::Skipped {
+                    stats.num_skipped += 1;
+                } else {
+                    stats.num_passed += 1;
+                }
+            }
+            stats.results.push(test);
+        }
+
+        stats
+    }
+
     fn num_failed(&self) -> usize {
         self.failed_tests.len()
     }
+
+    fn num_tests(&self) -> usize {
+        self.num_passed + self.num_skipped + self.num_failed()
+    }
+
+    /// Summary of skipped tests to append to result messages.
+    fn skipped_suffix(&self) -> String {
+        if self.num_skipped > 0 {
+            format!(", {} skipped", self.num_skipped)
+        } else {
+            String::new()
+        }
+    }
 }
 
-/// Sharded execution of a gtest executable
+/// Sharded execution of gtest executables
 ///
-/// This function takes the path to a gtest executable and number
-/// of shards. It then executes the tests in a sharded way and
-/// returns the number of failures.
-pub fn run<P: Into<PathBuf>>(
-    test_executable: P,
-    gtest_filter: Option<String>,
-    jobs: usize,
-    verbosity: u64,
-    repeat: u64,
-) -> Result<usize> {
+/// This function executes the tests of all test executables in `config` in a sharded way
+/// with at most `config.jobs` parallel shards. Progress and results are reported to all
+/// `reporters` as they happen, and a summary of all tests and their attempts is returned.
+pub fn run(config: &RunConfig, reporters: &mut [&mut dyn Reporter]) -> Result<RunSummary> {
+    let mut reporter = report::Reporters(reporters.iter_mut().map(|r| &mut **r as _).collect());
+    let mut schedule = config.schedule;
+    let mut summary = RunSummary::default();
+    summary.flaky = config.flaky;
+
+    // Timeouts too long to represent a deadline for never expire.
+    let deadline = config
+        .timeouts
+        .run
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+
     // We normalize the test executable path to decouple us from `Command::new` lookup semantics
     // and get the same results for when given `test-exe`, `./test-exe`, or `/path/to/test-exe`.
-    let test_executable = canonicalize(test_executable.into())?;
-
-    if let Some(filter) = gtest_filter {
-        env::set_var("GTEST_FILTER", filter);
-    }
-
-    // If we show some sort of progress bar determine the total number of tests before running shards.
-    let num_tests = {
+    let mut test_executables = config
+        .executables
+        .iter()
+        .map(|exe| {
+            canonicalize(exe).map_err(|source| Error::Spawn {
+                executable: exe.clone(),
+                source,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Run every test executable only once.
+    let mut seen = HashSet::new();
+    test_executables.retain(|exe| seen.insert(exe.clone()));
+
+    // Determine the tests to run before running shards.
+    let mut tests = {
         let span = info_span!("Determine number of tests");
         let _enter = span.enter();
         trace_scoped!("Determine number of tests");
 
-        let run_disabled_tests = match env::var("GTEST_ALSO_RUN_DISABLED_TESTS") {
-            Ok(val) => match val.parse::<i32>() {
-                Ok(b) => b > 0,
-                Err(_) => false,
-            },
-            Err(_) => false,
-        };
+        let run_disabled_tests = config
+            .var("GTEST_ALSO_RUN_DISABLED_TESTS")
+            .and_then(|val| val.to_str()?.parse::<i32>().ok())
+            .is_some_and(|b| b > 0);
 
-        let pb = ProgressBar::new(100);
+        reporter.listing_started(&test_executables);
+        test_executables
+            .iter()
+            .map(|exe| {
+                Ok((
+                    exe.clone(),
+                    exec::list_tests(config.command(exe), exe, run_disabled_tests)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
 
-        if verbosity < 1 {
-            pb.set_draw_target(ProgressDrawTarget::hidden());
+    let mut filtered = false;
+    for (exe, tests) in &mut tests {
+        let filter = config.filter_for(exe);
+        if !filter.is_empty() {
+            tests.retain(|test| filter.matches(test));
+            filtered = true;
         }
+    }
 
-        pb.set_style(ProgressStyle::default_spinner().template("{msg}")?);
-        pb.set_message("Determining number of tests ...");
-        let num = exec::get_tests(&test_executable, run_disabled_tests)?.len();
-        pb.finish_and_clear();
+    // gtest's sharding would select from all tests of an executable.
+    if filtered && schedule == Schedule::Shard {
+        schedule = Schedule::Queue;
+    }
 
-        num
-    };
+    // Tests using resources and serial tests are scheduled separately from other tests, which
+    // gtest's sharding cannot do.
+    if !(config.resources.is_empty() && config.serial.is_empty()) && schedule == Schedule::Shard {
+        schedule = Schedule::Queue;
+    }
 
-    // Do not execute more jobs than tests.
-    let jobs = min(jobs, num_tests);
+    let cache_dir = config.cache_dir.as_deref();
+    let (mut timings, mut failed) = match cache_dir {
+        Some(dir) => {
+            // Failing to load the cache should not fail the test run, like failing to update it.
+            let warn = |e: Error| eprintln!("Could not load cache from {}: {e}", dir.display());
+            (
+                cache::Timings::load(dir).unwrap_or_else(|e| {
+                    warn(e);
+                    cache::Timings::default()
+                }),
+                cache::FailedTests::load(dir).unwrap_or_else(|e| {
+                    warn(e);
+                    cache::FailedTests::default()
+                }),
+            )
+        }
+        None if config.rerun != Rerun::All => {
+            return Err(Error::MissingCacheDir);
+        }
+        None => Default::default(),
+    };
 
-    // Run tests.
-    let m = MultiProgress::new();
-    if !(1..=2).contains(&verbosity) {
-        m.set_draw_target(ProgressDrawTarget::hidden());
-    }
+    // Split off the tests which failed in the previous run.
+    let mut prioritized = vec![];
+    if config.rerun != Rerun::All {
+        for (exe, tests) in &mut tests {
+            let previously_failed;
+            (previously_failed, *tests) =
+                tests.drain(..).partition(|test| failed.contains(exe, test));
+            prioritized.push((exe.clone(), previously_failed));
+        }
 
-    let progress_global = Arc::new(m.add(ProgressBar::new(num_tests as u64)));
-    progress_global.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg} {bar} [{pos}/{len}] {elapsed_precise}")?,
-    );
-    progress_global.set_message("Running tests ...");
+        if config.rerun == Rerun::OnlyFailed {
+            tests = std::mem::take(&mut prioritized);
+        }
 
-    // Make sure the elapsed time is updated even if no updates arrive from shards.
-    progress_global.enable_steady_tick(Duration::from_millis(100));
+        // gtest's sharding would select from all tests of an executable.
+        if schedule == Schedule::Shard {
+            schedule = Schedule::Queue;
+        }
+    }
 
-    // Set up a communication channel between the worker processing test
-    // output threads and the main thread.
-    let (sender, receiver) = channel::unbounded();
-    let mut done_receivers = vec![];
+    let config = Arc::new(config.clone());
 
-    let mut progress_shards = vec![];
+    // Stress testing stops at the first failure instead of repeating failed tests.
+    let mut repeat = if config.stress.is_some() {
+        0
+    } else {
+        config.repeat
+    };
+    let stress_end = config
+        .stress
+        .and_then(|stress| stress.duration)
+        .and_then(|duration| Instant::now().checked_add(duration));
+    let mut iteration = 0;
+    loop {
+        iteration += 1;
+
+        let num_tests = tests
+            .iter()
+            .chain(&prioritized)
+            .map(|(_, tests)| tests.len())
+            .sum();
 
-    // Execute the shards.
-    for job in 0..jobs {
-        let (done_sender, done_receiver) = channel::unbounded();
-        done_receivers.push(done_receiver);
+        // Do not execute more jobs than tests.
+        let jobs = min(config.jobs, num_tests);
 
-        let cmd = exec::cmd(&test_executable, job, jobs).spawn()?;
+        let mut expected = tests
+            .iter()
+            .chain(&prioritized)
+            .flat_map(|(executable, tests)| {
+                tests.iter().map(|testcase| TestId {
+                    executable: executable.clone(),
+                    testcase: testcase.clone(),
+                })
+            })
+            .collect::<BTreeSet<_>>();
+
+        // Serial tests are run after all other tests, in a single process per executable.
+        let (parallel_prioritized, serial_prioritized) =
+            resources::split_serial(&config.serial, &prioritized);
+        let (parallel, serial) = resources::split_serial(&config.serial, &tests);
+        let serial_work = test_executables
+            .iter()
+            .filter_map(|exe| {
+                let tests = serial_prioritized
+                    .iter()
+                    .chain(&serial)
+                    .filter(|(executable, _)| executable == exe)
+                    .flat_map(|(_, tests)| tests.iter().cloned())
+                    .collect::<Vec<_>>();
+                (!tests.is_empty()).then(|| exec::Work::tests(exe.clone(), tests))
+            })
+            .collect();
+
+        // Schedule prioritized tests before anything else. Tests using the same resources never
+        // run concurrently, so every group of them is run by a single invocation, started early.
+        let mut work = vec![];
+        for (tests, schedule) in [
+            (&parallel_prioritized, Schedule::Queue),
+            (&parallel, schedule),
+        ] {
+            let (free, exclusive) = resources::split(&config.resources, tests);
+            work.extend(
+                exclusive
+                    .into_iter()
+                    .map(|(executable, tests)| exec::Work::tests(executable, tests)),
+            );
+            work.extend(schedule_work(&free, jobs, schedule, &timings));
+        }
+        if config.stress.is_none() {
+            prioritized.clear();
+        }
 
-        let progress_shard = if verbosity == 2 {
-            m.add(ProgressBar::new(100))
+        if config.stress.is_some() {
+            reporter.stress_iteration(iteration);
+        }
+        reporter.run_started(num_tests, jobs);
+        let (stats, mut exits) = run_work(work, jobs, &config, deadline, &mut reporter)?;
+        let failing = |stats: &ShardStats, exits: &[ShardExit]| {
+            !stats.failed_tests.is_empty() || exits.iter().any(|exit| exit.error.is_some())
+        };
+        let serial_work = if config.stress.is_some() && failing(&stats, &exits) {
+            vec![]
         } else {
-            ProgressBar::hidden()
+            serial_work
         };
-        progress_shard
-            .set_style(ProgressStyle::default_spinner().template("{spinner} {wide_msg}")?);
+        let (serial_stats, serial_exits) =
+            run_work(serial_work, 1, &config, deadline, &mut reporter)?;
+        let stats = ShardStats::new(
+            stats
+                .results
+                .into_iter()
+                .chain(serial_stats.results)
+                .collect(),
+        );
+        exits.extend(serial_exits);
+
+        // Only the last stress iteration is kept, which is the first failing one if any failed.
+        let stress_again = config.stress.is_some_and(|stress| {
+            let now = Instant::now();
+            !failing(&stats, &exits)
+                && stress.iterations.is_none_or(|n| iteration < n)
+                && stress_end.is_none_or(|end| now < end)
+                && deadline.is_none_or(|deadline| now < deadline)
+        });
+        if stress_again {
+            reporter.run_finished(&stats.results, None);
+            continue;
+        }
+        if config.stress.is_some() {
+            summary.stress_iterations = Some(iteration);
+        }
 
-        progress_shards.push(progress_shard);
+        // A failing stress iteration stops early, so tests which never started are not missing.
+        if config.stress.is_some() && failing(&stats, &exits) {
+            let reported = stats
+                .results
+                .iter()
+                .map(TestId::of)
+                .collect::<BTreeSet<_>>();
+            expected.retain(|id| reported.contains(id));
+        }
 
-        exec::process_shard(job, cmd, sender.clone(), done_sender)?;
-    }
+        summary.record(&expected, &stats.results);
+        summary
+            .shard_errors
+            .extend(exits.into_iter().filter(|exit| exit.error.is_some()));
 
-    // Close the sender in this thread.
-    drop(sender);
-
-    //////////////////////////////////////////
+        let last = repeat == 0 || stats.failed_tests.is_empty();
+        if last {
+            summary.timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        }
+        reporter.run_finished(&stats.results, last.then_some(&summary));
+
+        if !last {
+            repeat -= 1;
+
+            // Rerun only the failed tests. These are selected with explicit filters since gtest's
+            // sharding would select from all tests of an executable.
+            schedule = Schedule::Queue;
+            tests = test_executables
+                .iter()
+                .map(|exe| {
+                    let failed = stats
+                        .failed_tests
+                        .iter()
+                        .filter(|test| test.executable.as_ref() == Some(exe))
+                        .map(|test| test.testcase.clone())
+                        .collect();
+                    (exe.clone(), failed)
+                })
+                .collect();
+
+            continue;
+        }
 
-    // Report successes or failures globally.
-    let reporter = thread::spawn(move || {
-        let mut stats = ShardStats {
-            num_passed: 0,
-            failed_tests: vec![],
-        };
+        if let Some(dir) = cache_dir {
+            timings.update(&summary.results);
+            failed.update(&summary.results);
 
-        let mut sel = channel::Select::new();
-        for done in &done_receivers {
-            sel.recv(done);
+            // Failing to update the cache should not fail the test run.
+            if let Err(e) = timings.save(dir).and_then(|()| failed.save(dir)) {
+                eprintln!("Could not update cache in {}: {e}", dir.display());
+            }
         }
 
-        for result in receiver.iter() {
-            let shard = result.shard.unwrap();
-            let progress_shard = &progress_shards[shard];
-
-            progress_shard.inc(1);
+        return Ok(summary);
+    }
+}
 
-            if let Event::Terminal { log, .. } = &result.event {
-                if verbosity > 2 {
-                    for line in log {
-                        println!("{line}");
-                    }
+/// Distribute the tests of all executables over work items for `jobs` parallel jobs
+///
+/// Work items are ordered so that items estimated to take longest are started first which lets
+/// smaller items fill in once jobs become idle.
+fn schedule_work(
+    tests: &[(PathBuf, Vec<String>)],
+    jobs: usize,
+    schedule: Schedule,
+    timings: &cache::Timings,
+) -> Vec<exec::Work> {
+    let mut work = vec![];
+
+    match schedule {
+        Schedule::Shard => {
+            for (executable, tests) in tests {
+                let shards = min(jobs, tests.len());
+                for index in 0..shards {
+                    // Like gtest we assign tests to shards round-robin.
+                    work.push(exec::Work {
+                        executable: executable.clone(),
+                        tests: tests.iter().skip(index).step_by(shards).cloned().collect(),
+                        shard: Some((index, shards)),
+                    });
                 }
             }
-
-            match &result.event {
-                Event::Starting => {
-                    progress_shard.set_message(result.testcase);
+        }
+        Schedule::Queue => {
+            let num_tests = tests.iter().map(|(_, tests)| tests.len()).sum::<usize>();
+            let batch_size = max(1, num_tests / max(1, jobs * QUEUE_BATCHES_PER_JOB));
+            for (executable, tests) in tests {
+                for batch in tests.chunks(batch_size) {
+                    work.push(exec::Work::tests(executable.clone(), batch.to_vec()));
                 }
-                Event::Running => {}
-                Event::Terminal { status, .. } => {
-                    progress_global.inc(1);
-
-                    if status.is_failed() {
-                        progress_shard.set_message(format!("{}", style(&result.testcase).red()));
-
-                        stats.failed_tests.push(result.clone());
-                    } else {
-                        stats.num_passed += 1;
-                    }
+            }
+        }
+        Schedule::Balanced => {
+            for (executable, tests) in tests {
+                let groups = min(jobs, tests.len());
+
+                let estimate = timings.estimator(executable);
+                let mut order = (0..tests.len()).collect::<Vec<_>>();
+                order.sort_by_cached_key(|&i| Reverse(estimate(&tests[i])));
+
+                // Assign tests longest first to the group with the least total duration, or the
+                // least tests for groups with equal durations.
+                let mut loads = (0..groups)
+                    .map(|group| Reverse((Duration::ZERO, 0, group)))
+                    .collect::<BinaryHeap<_>>();
+                let mut assigned = vec![vec![]; groups];
+                for i in order {
+                    let Some(Reverse((load, num_tests, group))) = loads.pop() else {
+                        break;
+                    };
+                    assigned[group].push(i);
+                    loads.push(Reverse((load + estimate(&tests[i]), num_tests + 1, group)));
                 }
-            };
 
-            // Check if any shards can be cleaned up.
-            if let Ok(index) = sel.try_ready() {
-                sel.remove(index);
-                let progress_shard = &progress_shards[index];
-                progress_shard.finish_and_clear();
+                for mut group in assigned {
+                    // Keep the tests in the order the executable would run them.
+                    group.sort_unstable();
+                    work.push(exec::Work::tests(
+                        executable.clone(),
+                        group.into_iter().map(|i| tests[i].clone()).collect(),
+                    ));
+                }
             }
         }
+    }
 
-        progress_global.finish_and_clear();
-
-        stats
+    work.sort_by_cached_key(|work| {
+        let estimate = timings.estimator(&work.executable);
+        Reverse(
+            work.tests
+                .iter()
+                .map(|test| estimate(test))
+                .sum::<Duration>(),
+        )
     });
 
-    // This implicitly joins the waiter thread.
-    m.clear()?;
+    work
+}
+
+/// Execute work items with `jobs` parallel jobs while reporting progress to `reporter`
+fn run_work(
+    work: Vec<exec::Work>,
+    jobs: usize,
+    config: &Arc<RunConfig>,
+    deadline: Option<Instant>,
+    reporter: &mut dyn Reporter,
+) -> Result<(ShardStats, Vec<ShardExit>)> {
+    // Set up a communication channel between the worker processing test
+    // output threads and the main thread.
+    let (sender, receiver) = channel::unbounded();
 
-    // If we log only failures wait until all shards have finished processing.
-    if verbosity < 3 {
-        reporter.thread().unpark();
+    // Queue up the work for the shards, with filters short enough to be passed to the test
+    // executables.
+    let allocator = Arc::new(resources::Allocator::new(config.ports.clone()));
+    for work in work.into_iter().flat_map(exec::Work::split) {
+        exec::queue(&allocator, config, work);
     }
 
-    let stats = reporter.join().unwrap();
+    // Execute the shards.
+    let workers = (0..jobs)
+        .map(|job| {
+            exec::process_work(
+                job,
+                sender.clone(),
+                config.clone(),
+                allocator.clone(),
+                deadline,
+            )
+        })
+        .collect::<Vec<_>>();
 
-    if stats.failed_tests.is_empty() {
-        if verbosity > 0 {
-            let message = format!("{} tests passed", stats.num_passed);
-            println!("{}", style(message).bold().green());
-        }
-    } else {
-        if verbosity <= 2 {
-            for test in &stats.failed_tests {
-                if let Event::Terminal { status, log } = &test.event {
-                    if status.is_failed() {
-                        for line in log {
-                            println!("{line}");
-                        }
-                    }
+    // Close the sender in this thread.
+    drop(sender);
+
+    // Report progress until all workers are done.
+    let mut results = vec![];
+    let mut exits = vec![];
+    for message in receiver.iter() {
+        match message {
+            exec::Message::Test(test) => match &test.event {
+                Event::Starting => reporter.test_started(&test),
+                Event::Running => {}
+                Event::Terminal { .. } => {
+                    reporter.test_finished(&test);
+                    results.push(test);
                 }
+            },
+            exec::Message::ShardExited(exit) => {
+                reporter.shard_exited(&exit);
+                exits.push(exit);
             }
         }
-        let message = format!(
-            "{} out of {} tests failed",
-            stats.num_failed(),
-            stats.num_passed + stats.num_failed()
-        );
-        println!("{}", style(message).bold().red());
     }
 
-    if repeat != 0 && !stats.failed_tests.is_empty() {
-        let filter = stats
-            .failed_tests
-            .iter()
-            .fold("".to_string(), |acc, t| acc + ":" + &t.testcase);
-
-        return run(test_executable, Some(filter), jobs, verbosity, repeat - 1);
-    }
-
-    // Check that the number of reported tests is consistent with the number of expected tests.
-    // This mostly serves to validate that we did not accidentally drop test results.
-    let num_tests_reported = stats.num_failed() + stats.num_passed;
-    if num_tests != num_tests_reported {
-        eprintln!("Expected {num_tests} tests but only saw results from {num_tests_reported}",);
-
-        return Ok(1);
+    for worker in workers {
+        worker.join().unwrap()?;
     }
 
-    Ok(stats.num_failed())
+    Ok((ShardStats::new(results), exits))
 }
 
-pub fn test_executable() -> PathBuf {
-    std::env::current_exe().expect("could not determine path of current gtest-runner executable")
+pub fn test_executable() -> Result<PathBuf> {
+    Ok(std::env::current_exe()?)
 }
@@ -1,4 +1,14 @@
-use clap::Parser;
+use {
+    crate::{
+        config_file::{self, Settings},
+        Flaky, Format, Overrides, Result, Schedule,
+    },
+    clap::{
+        builder::BoolishValueParser, parser::ValueSource, CommandFactory, FromArgMatches, Parser,
+    },
+    regex::Regex,
+    std::{ops::RangeInclusive, path::PathBuf, time::Duration},
+};
 
 #[derive(Parser, Debug, Default)]
 pub struct Opt {
@@ -41,30 +51,435 @@ pub struct Opt {
     #[clap(long, short)]
     pub trace: bool,
 
+    /// Output format
+    ///
+    /// With `jsonl` every test start and result is written to stdout as a JSON object on its own
+    /// line as soon as it happens, instead of progress bars and summaries. Objects have the fields
+    /// `event` (`starting` or `terminal`), `test`, `executable`, and `shard`; terminal events
+    /// additionally contain `status`, `duration_ms`, and `log`. Invocations of test executables
+    /// which failed outside of any test are reported with `event` `shard_error` and the fields
+    /// `executable`, `shard`, `error`, and `log`.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "console",
+        env = "GTEST_RUNNER_FORMAT"
+    )]
+    pub format: Format,
+
+    /// Run every test executable invocation in its own working directory
+    ///
+    /// Every invocation of a test executable gets its own fresh temporary directory in `TMPDIR`
+    /// and `TEST_TMPDIR`. With this flag it is additionally run in its own fresh working
+    /// directory. These directories are removed if all tests of the invocation passed, and kept
+    /// otherwise, with their path added to the log of the failed tests.
+    #[clap(
+        long,
+        value_parser = BoolishValueParser::new(),
+        env = "GTEST_RUNNER_ISOLATE_WORKING_DIR"
+    )]
+    pub isolate_working_dir: bool,
+
+    /// Number of local ports handed to every test executable invocation
+    ///
+    /// If this flag is given every invocation of a test executable gets its own range of
+    /// consecutive local ports from `port_range`, disjoint from the ranges of all other running
+    /// invocations and free when the invocation starts. The first port is exported to the test
+    /// executable as `GTEST_RUNNER_PORT_BASE`, the number of ports as `GTEST_RUNNER_PORT_COUNT`.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u16).range(1..),
+        env = "GTEST_RUNNER_PORTS_PER_SHARD"
+    )]
+    pub ports_per_shard: Option<u16>,
+
+    /// Local ports to hand out to test executables, see `ports_per_shard`
+    ///
+    /// The range is given as `first-last` and defaults to `20000-29999`.
+    #[clap(long, value_parser = parse_port_range, env = "GTEST_RUNNER_PORT_RANGE")]
+    pub port_range: Option<RangeInclusive<u16>>,
+
+    /// Exclusive resource used by tests, as `name=pattern`
+    ///
+    /// Tests matching the gtest filter `pattern` use the resource `name`, and never run
+    /// concurrently with other tests using the same resource, e.g., `db=Database*`. This flag can
+    /// be given multiple times; in the environment variable resources are separated by `,`.
+    #[clap(
+        long = "resource",
+        value_parser = parse_resource,
+        value_delimiter = ',',
+        env = "GTEST_RUNNER_RESOURCES"
+    )]
+    pub resources: Vec<(String, String)>,
+
+    /// Tests to run serially, as gtest filter pattern
+    ///
+    /// Tests matching the pattern are run after all other tests, one test executable at a time,
+    /// e.g., because they use a device shared by all tests. This flag can be given multiple times;
+    /// in the environment variable patterns are separated by `,`.
+    #[clap(long, value_delimiter = ',', env = "GTEST_RUNNER_SERIAL")]
+    pub serial: Vec<String>,
+
+    /// Run test executables under a wrapper command
+    ///
+    /// The wrapper is split at whitespace and the path of the test executable is appended, e.g.,
+    /// with `valgrind --error-exitcode=1` every test executable is run under memcheck. The wrapper
+    /// is used both to list and to run tests. Its output is attached to the test running at the
+    /// time, or otherwise reported for the invocation if it fails.
+    #[clap(long, env = "GTEST_RUNNER_WRAPPER")]
+    pub wrapper: Option<String>,
+
+    /// Write a JUnit XML report to the given path
+    ///
+    /// The report contains the results of the tests from all test executables. Tests repeated
+    /// because of `repeat` are reported once per attempt.
+    #[clap(long, env = "GTEST_RUNNER_JUNIT")]
+    pub junit: Option<PathBuf>,
+
+    /// Only run tests matching a gtest filter
+    ///
+    /// The filter uses gtest's syntax, i.e., `:`-separated positive patterns optionally followed
+    /// by `-` and `:`-separated negative patterns, where patterns can contain the wildcards `*`
+    /// and `?`, e.g., `Suite.*-Suite.Slow*`. The filter is applied to the tests of every test
+    /// executable.
+    #[clap(long, env = "GTEST_RUNNER_FILTER")]
+    pub filter: Option<String>,
+
+    /// Only run tests whose name matches a regular expression
+    ///
+    /// Test names have the form `Suite.Test`. If also `filter` is given tests need to match both.
+    #[clap(long, value_parser = Regex::new, env = "GTEST_RUNNER_FILTER_REGEX")]
+    pub filter_regex: Option<Regex>,
+
     /// Repeat failed tests
     ///
     /// If this flag is given a non-zero value, failed tests will be repeated up to `repeat` times.
     #[clap(long, short, default_value = "0", env = "GTEST_RUNNER_REPEAT")]
     pub repeat: u64,
 
+    /// Run tests repeatedly until the first failure
+    ///
+    /// All selected tests are run up to the given number of iterations, each parallelized over all
+    /// jobs like a normal run. Testing stops at the first failure, without starting further tests;
+    /// only the results of that iteration are reported, together with its number. This takes
+    /// precedence over `repeat`.
+    #[clap(long, env = "GTEST_RUNNER_STRESS")]
+    pub stress: Option<u64>,
+
+    /// Run tests repeatedly until the first failure or for the given duration
+    ///
+    /// Like `stress`, but no further iterations are started once the duration has passed, e.g.,
+    /// `10m`. If also `stress` is given testing stops at whichever limit is reached first.
+    #[clap(long, value_parser = parse_duration, env = "GTEST_RUNNER_STRESS_DURATION")]
+    pub stress_duration: Option<Duration>,
+
+    /// Exit code for flaky tests
+    ///
+    /// A test is flaky if it failed but then passed when repeated because of `repeat`. Flaky tests
+    /// are listed separately at the end of the run. With `pass` they do not affect the exit code,
+    /// with `fail` they are counted as failed.
+    #[clap(long, value_enum, default_value = "pass", env = "GTEST_RUNNER_FLAKY")]
+    pub flaky: Flaky,
+
+    /// Test scheduling strategy
+    ///
+    /// With `shard` every job executes a fixed shard of the tests using gtest's native sharding.
+    /// This requires the least number of test process spawns, but a single slow shard can hold up
+    /// the whole run while other jobs are idle.
+    ///
+    /// With `queue` tests are handed out in small batches to jobs whenever they become idle so
+    /// that all jobs are kept busy until all tests have run.
+    ///
+    /// With `balanced` tests are assigned to jobs longest first based on their durations in
+    /// previous runs (see `cache-dir`) so that all jobs finish at about the same time.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "shard",
+        env = "GTEST_RUNNER_SCHEDULE"
+    )]
+    pub schedule: Schedule,
+
+    /// Number of slowest tests to report
+    ///
+    /// After all tests have run the given number of slowest tests is listed with their durations.
+    /// Durations are taken from the test output if available, and otherwise measured by the
+    /// runner. Setting this to zero disables the report.
+    #[clap(long, default_value = "5", env = "GTEST_RUNNER_SLOWEST")]
+    pub slowest: usize,
+
+    /// Per-test timeout
+    ///
+    /// If a single test runs longer than this its test process is killed and the test is reported
+    /// as timed out. The remaining tests of the same shard are then run in a new process.
+    ///
+    /// Durations are given as a number with an optional unit `ms`, `s`, `m`, or `h`, e.g., `90s`
+    /// or `5m`. Without unit the duration is in seconds.
+    #[clap(long, value_parser = parse_duration, env = "GTEST_RUNNER_TIMEOUT")]
+    pub timeout: Option<Duration>,
+
+    /// Timeout for the whole test run
+    ///
+    /// If running all tests takes longer than this all running tests are killed and reported as
+    /// timed out, and no further tests are started.
+    #[clap(long, value_parser = parse_duration, env = "GTEST_RUNNER_RUN_TIMEOUT")]
+    pub run_timeout: Option<Duration>,
+
+    /// Run tests which failed in the previous run first
+    ///
+    /// Tests which failed in the previous run of a test executable (see `cache-dir`) are
+    /// started before all other tests.
+    #[clap(
+        long,
+        conflicts_with = "only_failed",
+        value_parser = BoolishValueParser::new(),
+        env = "GTEST_RUNNER_FAILED_FIRST"
+    )]
+    pub failed_first: bool,
+
+    /// Only run tests which failed in the previous run
+    ///
+    /// Only tests which failed in the previous run of a test executable (see `cache-dir`) are
+    /// run. Tests which pass are removed from the set of failed tests.
+    #[clap(
+        long,
+        value_parser = BoolishValueParser::new(),
+        env = "GTEST_RUNNER_ONLY_FAILED"
+    )]
+    pub only_failed: bool,
+
+    /// Directory for state kept between runs
+    ///
+    /// Test durations and failed tests are recorded here after every run. Durations are used to
+    /// balance tests across jobs with `--schedule balanced`, and failed tests can be rerun with
+    /// `--failed-first` or `--only-failed`. By default this is `gtest-runner` in the user's cache
+    /// directory, e.g., `~/.cache/gtest-runner`.
+    #[clap(long, env = "GTEST_RUNNER_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Config file with defaults for options
+    ///
+    /// Options given on the command line or in environment variables take precedence over the
+    /// config file. The config file can set `jobs`, `repeat`, `verbosity`, `filter`,
+    /// `filter_regex`, `timeout`, `run_timeout`, and `serial` (a list of patterns), resources as
+    /// `name = "pattern"` in a table `resource`, environment variables for the test
+    /// executables in a table `env`, and overrides for single test executables in tables
+    /// `executables."path/to/test-exe"` like in a `manifest`, where settings of the manifest take
+    /// precedence. By default `.gtest-runner.toml` is used from the current directory or its
+    /// closest ancestor containing one.
+    #[clap(long, env = "GTEST_RUNNER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Environment variables set for the test executables, from the config file
+    #[clap(skip)]
+    pub env: Vec<(String, String)>,
+
+    /// Overrides for single test executables, from the config file
+    #[clap(skip)]
+    pub overrides: Vec<(PathBuf, Overrides)>,
+
     #[clap(flatten)]
     pub mode: RunMode,
 }
 
+impl Opt {
+    /// Parse options from the command line and environment with defaults from the config file
+    ///
+    /// Like `Opt::parse` this exits the process if the command line is invalid.
+    pub fn parse_with_config_file() -> Result<Opt> {
+        let matches = Opt::command().get_matches();
+        let mut opt = Opt::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        let path = match &opt.config {
+            Some(path) => Some(path.clone()),
+            None => config_file::discover(&std::env::current_dir()?),
+        };
+        if let Some(path) = path {
+            let settings = config_file::load(&path)?;
+            opt.apply(settings, |id| {
+                matches!(
+                    matches.value_source(id),
+                    None | Some(ValueSource::DefaultValue)
+                )
+            });
+        }
+
+        Ok(opt)
+    }
+
+    /// Use the `settings` of a config file for all options for which `unset` returns true.
+    fn apply(&mut self, settings: Settings, unset: impl Fn(&str) -> bool) {
+        if unset("jobs") {
+            self.jobs = settings.jobs.or(self.jobs);
+        }
+        if unset("repeat") {
+            self.repeat = settings.repeat.unwrap_or(self.repeat);
+        }
+        if unset("verbosity") {
+            self.verbosity = settings.verbosity.unwrap_or(self.verbosity);
+        }
+        if unset("filter") {
+            self.filter = settings.filter.or(self.filter.take());
+        }
+        if unset("filter_regex") {
+            self.filter_regex = settings.filter_regex.or(self.filter_regex.take());
+        }
+        if unset("timeout") {
+            self.timeout = settings.timeout.or(self.timeout);
+        }
+        if unset("run_timeout") {
+            self.run_timeout = settings.run_timeout.or(self.run_timeout);
+        }
+        if unset("serial") {
+            self.serial = settings.serial.unwrap_or(std::mem::take(&mut self.serial));
+        }
+        if unset("resources") {
+            self.resources = settings
+                .resources
+                .unwrap_or(std::mem::take(&mut self.resources));
+        }
+        self.env = settings.env;
+        self.overrides = settings.overrides;
+    }
+}
+
 #[derive(clap::Args, Default, Debug)]
 #[group(required = true, multiple = false)]
 pub struct RunMode {
     /// GTest executable(s)
     ///
-    /// The test runner can execute tests from the same executable in parallel, but will currently
-    /// not run different test executables in parallel. In order for tests to be executable in
-    /// parallel they likely should not depend on system information (e.g., the ability to bind to
-    /// fixed ports; the presence or absence of especially test-created files in fixed file system
-    /// locations, etc.).
+    /// The test runner executes tests from all given executables in parallel, sharing the
+    /// available `jobs` between them. In order for tests to be executable in parallel they likely
+    /// should not depend on system information (e.g., the ability to bind to fixed ports; the
+    /// presence or absence of especially test-created files in fixed file system locations,
+    /// etc.). Tests which write to `TMPDIR` or `TEST_TMPDIR` are isolated from each other, see
+    /// `isolate_working_dir`.
     #[clap(required = true)]
     pub test_executables: Option<Vec<String>>,
 
+    /// Manifest listing the test executables to run with per-executable overrides
+    ///
+    /// The manifest is a TOML file with a table `executables."path/to/test-exe"` for every test
+    /// executable, with paths relative to the manifest. Tables can override `filter`,
+    /// `filter_regex`, and `timeout`, cap the number of concurrent invocations of the executable
+    /// with `jobs`, and pass extra arguments `args` and environment variables `env` to it.
+    #[clap(long, env = "GTEST_RUNNER_MANIFEST")]
+    pub manifest: Option<PathBuf>,
+
     /// Provide sample GTest data for testing.
     #[clap(long, env = "GTEST_RUNNER_SAMPLE_DATA")]
     pub sample_data: Option<bool>,
 }
+
+/// Parse a range of ports given as `first-last`
+fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (first, last) = s
+        .split_once('-')
+        .ok_or_else(|| format!("invalid port range '{s}', expected 'first-last'"))?;
+    let port = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid port '{port}': {e}"))
+    };
+
+    let range = port(first)?..=port(last)?;
+    if range.is_empty() {
+        return Err(format!("empty port range '{s}'"));
+    }
+    Ok(range)
+}
+
+/// Parse an exclusive resource given as `name=pattern`
+fn parse_resource(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, pattern)) if !name.is_empty() && !pattern.is_empty() => {
+            Ok((name.into(), pattern.into()))
+        }
+        _ => Err(format!("invalid resource '{s}', expected 'name=pattern'")),
+    }
+}
+
+/// Parse a duration with an optional unit suffix
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| format!("invalid duration '{s}': {e}"))?;
+
+    let scale = match unit {
+        "ms" => 1e-3,
+        "" | "s" => 1.,
+        "m" => 60.,
+        "h" => 3600.,
+        _ => return Err(format!("invalid duration unit '{unit}'")),
+    };
+
+    Duration::try_from_secs_f64(value * scale).map_err(|e| format!("invalid duration '{s}': {e}"))
+}
+
+#[test]
+fn test_apply() {
+    let settings = || Settings {
+        jobs: Some(4),
+        verbosity: Some(0),
+        filter: Some("A.*".into()),
+        serial: Some(vec!["Device*".into()]),
+        resources: Some(vec![("db".into(), "Db*".into())]),
+        env: vec![("A".into(), "a".into())],
+        ..Settings::default()
+    };
+
+    let mut opt = Opt {
+        jobs: Some(2),
+        verbosity: 2,
+        ..Opt::default()
+    };
+    opt.apply(settings(), |_| true);
+    assert_eq!(Some(4), opt.jobs);
+    assert_eq!(0, opt.verbosity);
+    assert_eq!(Some("A.*"), opt.filter.as_deref());
+    assert_eq!(0, opt.repeat);
+    assert_eq!(vec!["Device*"], opt.serial);
+    assert_eq!(vec![("db".to_string(), "Db*".to_string())], opt.resources);
+    assert_eq!(vec![("A".to_string(), "a".to_string())], opt.env);
+
+    // Options given on the command line are kept.
+    let mut opt = Opt {
+        jobs: Some(2),
+        serial: vec!["Slow*".into()],
+        ..Opt::default()
+    };
+    opt.apply(settings(), |id| id != "jobs" && id != "serial");
+    assert_eq!(Some(2), opt.jobs);
+    assert_eq!(0, opt.verbosity);
+    assert_eq!(vec!["Slow*"], opt.serial);
+}
+
+#[test]
+fn test_parse_port_range() {
+    assert_eq!(Ok(20000..=29999), parse_port_range("20000-29999"));
+    assert!(parse_port_range("20000").is_err());
+    assert!(parse_port_range("30000-20000").is_err());
+    assert!(parse_port_range("20000-70000").is_err());
+
+    assert_eq!(
+        Ok(("db".into(), "Db*:Sql*".into())),
+        parse_resource("db=Db*:Sql*")
+    );
+    assert!(parse_resource("db").is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90"));
+    assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90s"));
+    assert_eq!(Ok(Duration::from_millis(1500)), parse_duration("1.5s"));
+    assert_eq!(Ok(Duration::from_millis(250)), parse_duration("250ms"));
+    assert_eq!(Ok(Duration::from_secs(600)), parse_duration("10m"));
+    assert_eq!(Ok(Duration::from_secs(7200)), parse_duration("2h"));
+    assert!(parse_duration("10d").is_err());
+    assert!(parse_duration("-1s").is_err());
+    assert!(parse_duration("s").is_err());
+}
@@ -1,7 +1,7 @@
 use {
     crate::{Event, Status},
-    anyhow::{anyhow, Result},
     console::strip_ansi_codes,
+    std::time::Duration,
 };
 
 #[cfg(test)]
@@ -20,41 +20,64 @@ pub struct Parser<T> {
     test: Option<Test>,
     reader: T,
 
+    // Output which is not part of any test.
+    output: Vec<String>,
+
     starting: regex::Regex,
     ok: regex::Regex,
     failed: regex::Regex,
+    skipped: regex::Regex,
 }
 
 impl<T> Parser<T> {
-    fn parse(&mut self, line: &str) -> Result<Option<crate::Test>> {
+    fn parse(&mut self, line: &str) -> Option<crate::Test> {
         let line = strip_ansi_codes(line).to_string();
 
         if let Some(test) = &mut self.test {
             test.log.push(line.clone());
+        } else if !self.starting.is_match(&line) {
+            self.output.push(line.clone());
         }
 
         let mut result = None;
 
         if let Some(test) = &self.test {
             let test = test.clone();
-            if self.ok.is_match(&line) {
+            if let Some(captures) = self.ok.captures(&line) {
                 result = Some(crate::Test {
                     testcase: test.case,
                     shard: None,
+                    executable: None,
                     event: Event::Terminal {
                         status: Status::Ok,
                         log: test.log,
+                        duration: parse_duration(&captures),
                     },
                 });
 
                 self.test = None;
-            } else if self.failed.is_match(&line) {
+            } else if let Some(captures) = self.failed.captures(&line) {
                 result = Some(crate::Test {
                     testcase: test.case,
                     shard: None,
+                    executable: None,
                     event: Event::Terminal {
                         status: Status::Failed,
                         log: test.log,
+                        duration: parse_duration(&captures),
+                    },
+                });
+
+                self.test = None;
+            } else if let Some(captures) = self.skipped.captures(&line) {
+                result = Some(crate::Test {
+                    testcase: test.case,
+                    shard: None,
+                    executable: None,
+                    event: Event::Terminal {
+                        status: Status::Skipped,
+                        log: test.log,
+                        duration: parse_duration(&captures),
                     },
                 });
 
@@ -63,18 +86,12 @@ impl<T> Parser<T> {
                 result = Some(crate::Test {
                     testcase: test.case,
                     shard: None,
+                    executable: None,
                     event: Event::Running,
                 });
             }
-        } else if self.starting.is_match(&line) {
-            let case = String::from(
-                strip_ansi_codes(&line).to_string()[12..]
-                    .split_whitespace()
-                    .next()
-                    .ok_or_else(|| {
-                        anyhow!("Expected at least a single space in line: {}", &line)
-                    })?,
-            );
+        } else if let Some(captures) = self.starting.captures(&line) {
+            let case = captures[1].to_string();
             self.test = Some(Test {
                 case: case.clone(),
                 log: vec![line],
@@ -83,11 +100,12 @@ impl<T> Parser<T> {
             result = Some(crate::Test {
                 testcase: case,
                 shard: None,
+                executable: None,
                 event: Event::Starting,
             });
         };
 
-        Ok(result)
+        result
     }
 
     fn finalize(&mut self) -> Option<crate::Test> {
@@ -96,9 +114,11 @@ impl<T> Parser<T> {
             let result = crate::Test {
                 testcase: test.case.clone(),
                 shard: None,
+                executable: None,
                 event: Event::Terminal {
                     status: Status::Aborted,
                     log: test.log.clone(),
+                    duration: None,
                 },
             };
 
@@ -109,6 +129,11 @@ impl<T> Parser<T> {
 
         None
     }
+
+    /// Output seen so far which was not part of any test, e.g., from global set-up
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
 }
 
 impl<T> Parser<T>
@@ -119,14 +144,24 @@ where
         Parser {
             test: None,
             reader,
+            output: vec![],
 
-            starting: regex::Regex::new(r"^\[ RUN      \] .*").unwrap(),
-            ok: regex::Regex::new(r"^\[       OK \] .* \(\d* .*\)").unwrap(),
-            failed: regex::Regex::new(r"^\[  FAILED  \] .* \(\d* .*\)").unwrap(),
+            starting: regex::Regex::new(r"^\[ RUN      \] \s*(\S+)").unwrap(),
+            ok: regex::Regex::new(r"^\[       OK \] .* \((\d*) .*\)").unwrap(),
+            failed: regex::Regex::new(r"^\[  FAILED  \] .* \((\d*) .*\)").unwrap(),
+            skipped: regex::Regex::new(r"^\[  SKIPPED \] .* \((\d*) .*\)").unwrap(),
         }
     }
 }
 
+/// Extract the test duration gtest reports in milliseconds.
+fn parse_duration(captures: &regex::Captures) -> Option<Duration> {
+    captures
+        .get(1)
+        .and_then(|ms| ms.as_str().parse().ok())
+        .map(Duration::from_millis)
+}
+
 impl<T> Iterator for Parser<T>
 where
     T: Iterator<Item = String>,
@@ -134,10 +169,12 @@ where
     type Item = crate::Test;
 
     fn next(&mut self) -> Option<crate::Test> {
-        match self.reader.next() {
-            Some(line) => self.parse(&line).ok()?.or_else(|| self.next()),
-            None => self.finalize(),
+        while let Some(line) = self.reader.next() {
+            if let Some(test) = self.parse(&line) {
+                return Some(test);
+            }
         }
+        self.finalize()
     }
 }
 
@@ -148,13 +185,17 @@ fn test_parse_one() {
 [----------] Global test environment set-up.
 [----------] 3 tests from NOPE
 [ RUN      ] NOPE.NOPE1
-[       OK ] NOPE.NOPE1 (0 ms)
+[       OK ] NOPE.NOPE1 (12 ms)
 [ RUN      ] NOPE.NOPE2
 ../3rdparty/libprocess/src/tests/future_tests.cpp:886: Failure
 Value of: false
   Actual: false
 Expected: true
 [  FAILED  ] NOPE.NOPE2 (0 ms)
+[ RUN      ] NOPE.NOPE4
+../3rdparty/libprocess/src/tests/future_tests.cpp:890: Skipped
+
+[  SKIPPED ] NOPE.NOPE4 (1 ms)
 [ RUN      ] NOPE.NOPE3
 WARNING: Logging before InitGoogleLogging() is written to STDERR
 F0303 10:01:07.804791 2590810944 future_tests.cpp:892] Check failed: false
@@ -183,8 +224,20 @@ PC: @     0x7fff617c3e3e __pthread_kill
     @     0x7fff61674115 start
     @                0x2 (unknown)"#;
 
+    let mut parser = Parser::new(output.split('\n').map(String::from));
+    parser.by_ref().for_each(drop);
     assert_eq!(
-        vec!["NOPE.NOPE1", "NOPE.NOPE2", "NOPE.NOPE3"],
+        vec![
+            "Note: Google Test filter = *NOPE*-",
+            "[==========] Running 3 tests from 1 test case.",
+            "[----------] Global test environment set-up.",
+            "[----------] 3 tests from NOPE",
+        ],
+        parser.output()
+    );
+
+    assert_eq!(
+        vec!["NOPE.NOPE1", "NOPE.NOPE2", "NOPE.NOPE4", "NOPE.NOPE3"],
         Vec::from_iter(
             Parser::new(output.split('\n').map(String::from))
                 .filter(|result| matches!(result.event, Event::Starting))
@@ -205,6 +258,23 @@ PC: @     0x7fff617c3e3e __pthread_kill
         )
     );
 
+    assert_eq!(
+        vec![
+            Some(Duration::from_millis(12)),
+            Some(Duration::ZERO),
+            Some(Duration::from_millis(1)),
+            None
+        ],
+        Vec::from_iter(
+            Parser::new(output.split('\n').map(String::from)).filter_map(|result| {
+                match result.event {
+                    Event::Terminal { duration, .. } => Some(duration),
+                    _ => None,
+                }
+            }),
+        )
+    );
+
     assert_eq!(
         vec!["NOPE.NOPE2"],
         Vec::from_iter(
@@ -217,6 +287,18 @@ PC: @     0x7fff617c3e3e __pthread_kill
         )
     );
 
+    assert_eq!(
+        vec!["NOPE.NOPE4"],
+        Vec::from_iter(
+            Parser::new(output.split('\n').map(String::from))
+                .filter(|result| match &result.event {
+                    Event::Terminal { status, .. } => *status == Status::Skipped,
+                    _ => false,
+                })
+                .map(|result| result.testcase),
+        )
+    );
+
     let aborted = Vec::from_iter(Parser::new(output.split('\n').map(String::from)).filter(
         |result| match &result.event {
             Event::Terminal { status, .. } => *status == Status::Aborted,
@@ -269,3 +351,43 @@ PC: @     0x7fff617c3e3e __pthread_kill
             .unwrap()
     );
 }
+
+#[test]
+fn test_parse_malformed() {
+    let output = "[ RUN      ] \n[ RUN      ] NOPE.NOPE1\n[       OK ] NOPE.NOPE1 (1 ms)";
+
+    let mut parser = Parser::new(output.split('\n').map(String::from));
+    assert_eq!(
+        vec!["NOPE.NOPE1"],
+        Vec::from_iter(
+            parser
+                .by_ref()
+                .filter(|result| matches!(result.event, Event::Terminal { .. }))
+                .map(|result| result.testcase),
+        )
+    );
+    assert_eq!(vec!["[ RUN      ] "], parser.output());
+}
+
+#[test]
+fn test_parse_long_output() {
+    // Lots of output outside of tests, e.g., logging on stderr, does not exhaust the stack.
+    let lines = (0..100_000)
+        .map(|i| format!("I0303 10:01:07.804791 sample.cc:1] line {i}"))
+        .chain([
+            "[ RUN      ] NOPE.NOPE1".to_string(),
+            "[       OK ] NOPE.NOPE1 (1 ms)".to_string(),
+        ]);
+
+    let mut parser = Parser::new(lines);
+    assert_eq!(
+        vec!["NOPE.NOPE1"],
+        Vec::from_iter(
+            parser
+                .by_ref()
+                .filter(|result| matches!(result.event, Event::Terminal { .. }))
+                .map(|result| result.testcase),
+        )
+    );
+    assert_eq!(100_000, parser.output().len());
+}
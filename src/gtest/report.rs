@@ -0,0 +1,408 @@
+use {
+    crate::{Event, Outcome, RunSummary, ShardExit, ShardStats, Test},
+    console::style,
+    indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle},
+    std::{cmp::Reverse, path::PathBuf, time::Duration},
+};
+
+/// Receiver of the progress and results of test runs
+///
+/// All callbacks are invoked on the thread calling `run`, in the order in which events happen.
+/// With `repeat` failed tests are run again, so a single call to `run` can consist of multiple
+/// runs which are each reported from `run_started` to `run_finished`.
+pub trait Reporter {
+    /// Tests of the given test executables are being listed.
+    fn listing_started(&mut self, _executables: &[PathBuf]) {}
+
+    /// An iteration of stress testing is starting. Every iteration is a separate run.
+    fn stress_iteration(&mut self, _iteration: u64) {}
+
+    /// A run of `num_tests` tests on `jobs` parallel jobs is starting.
+    fn run_started(&mut self, _num_tests: usize, _jobs: usize) {}
+
+    /// A test started.
+    fn test_started(&mut self, _test: &Test) {}
+
+    /// A test finished with a terminal result.
+    fn test_finished(&mut self, _test: &Test) {}
+
+    /// An invocation of a test executable exited.
+    fn shard_exited(&mut self, _exit: &ShardExit) {}
+
+    /// A run finished with the given terminal results.
+    ///
+    /// With the last run the `summary` of all runs is passed, and no further runs follow.
+    fn run_finished(&mut self, _results: &[Test], _summary: Option<&RunSummary>) {}
+}
+
+/// Reporter forwarding to multiple reporters in order
+pub(crate) struct Reporters<'a>(pub Vec<&'a mut dyn Reporter>);
+
+impl Reporter for Reporters<'_> {
+    fn listing_started(&mut self, executables: &[PathBuf]) {
+        for reporter in &mut self.0 {
+            reporter.listing_started(executables);
+        }
+    }
+
+    fn stress_iteration(&mut self, iteration: u64) {
+        for reporter in &mut self.0 {
+            reporter.stress_iteration(iteration);
+        }
+    }
+
+    fn run_started(&mut self, num_tests: usize, jobs: usize) {
+        for reporter in &mut self.0 {
+            reporter.run_started(num_tests, jobs);
+        }
+    }
+
+    fn test_started(&mut self, test: &Test) {
+        for reporter in &mut self.0 {
+            reporter.test_started(test);
+        }
+    }
+
+    fn test_finished(&mut self, test: &Test) {
+        for reporter in &mut self.0 {
+            reporter.test_finished(test);
+        }
+    }
+
+    fn shard_exited(&mut self, exit: &ShardExit) {
+        for reporter in &mut self.0 {
+            reporter.shard_exited(exit);
+        }
+    }
+
+    fn run_finished(&mut self, results: &[Test], summary: Option<&RunSummary>) {
+        for reporter in &mut self.0 {
+            reporter.run_finished(results, summary);
+        }
+    }
+}
+
+/// Progress bars and summaries for humans on stdout
+pub struct Console {
+    verbosity: u64,
+    slowest: usize,
+    show_executables: bool,
+
+    progress: MultiProgress,
+    progress_global: ProgressBar,
+    progress_shards: Vec<ProgressBar>,
+
+    // Invocations of the current run which failed outside of any test.
+    shard_errors: Vec<ShardExit>,
+
+    // Current iteration when stress testing.
+    stress_iteration: Option<u64>,
+}
+
+impl Console {
+    /// Create a console reporter
+    ///
+    /// See `Opt::verbosity` for the meaning of `verbosity`. At the end the `slowest` tests are
+    /// listed.
+    pub fn new(verbosity: u64, slowest: usize) -> Console {
+        Console {
+            verbosity,
+            slowest,
+            show_executables: false,
+            progress: MultiProgress::new(),
+            progress_global: ProgressBar::hidden(),
+            progress_shards: vec![],
+            shard_errors: vec![],
+            stress_iteration: None,
+        }
+    }
+}
+
+impl Reporter for Console {
+    fn listing_started(&mut self, executables: &[PathBuf]) {
+        // Failed tests are attributed to their executable if there are multiple.
+        self.show_executables = executables.len() > 1;
+
+        if self.verbosity < 1 {
+            return;
+        }
+
+        self.progress_global = ProgressBar::new(100);
+        self.progress_global.set_style(progress_style("{msg}"));
+        self.progress_global
+            .set_message("Determining number of tests ...");
+    }
+
+    fn stress_iteration(&mut self, iteration: u64) {
+        self.stress_iteration = Some(iteration);
+    }
+
+    fn run_started(&mut self, num_tests: usize, jobs: usize) {
+        self.progress_global.finish_and_clear();
+
+        self.progress = MultiProgress::new();
+        if !(1..=2).contains(&self.verbosity) {
+            self.progress.set_draw_target(ProgressDrawTarget::hidden());
+        }
+
+        self.progress_global = self.progress.add(ProgressBar::new(num_tests as u64));
+        self.progress_global.set_style(progress_style(
+            "{spinner:.green} {msg} {bar} [{pos}/{len}] {elapsed_precise}",
+        ));
+        self.progress_global
+            .set_message(match self.stress_iteration {
+                Some(iteration) => format!("Stress iteration {iteration} ..."),
+                None => "Running tests ...".into(),
+            });
+
+        // Make sure the elapsed time is updated even if no updates arrive from shards.
+        self.progress_global
+            .enable_steady_tick(Duration::from_millis(100));
+
+        self.progress_shards = (0..jobs)
+            .map(|_| {
+                let progress_shard = if self.verbosity == 2 {
+                    self.progress.add(ProgressBar::new(100))
+                } else {
+                    ProgressBar::hidden()
+                };
+                progress_shard.set_style(progress_style("{spinner} {wide_msg}"));
+                progress_shard
+            })
+            .collect();
+    }
+
+    fn test_started(&mut self, test: &Test) {
+        if let Some(progress_shard) = test.shard.and_then(|shard| self.progress_shards.get(shard)) {
+            progress_shard.inc(1);
+            progress_shard.set_message(test.testcase.clone());
+        }
+    }
+
+    fn test_finished(&mut self, test: &Test) {
+        let Event::Terminal { status, log, .. } = &test.event else {
+            return;
+        };
+
+        if self.verbosity > 2 {
+            for line in log {
+                println!("{line}");
+            }
+        }
+
+        self.progress_global.inc(1);
+
+        if let Some(progress_shard) = test.shard.and_then(|shard| self.progress_shards.get(shard)) {
+            progress_shard.inc(1);
+            if status.is_failed() {
+                progress_shard.set_message(format!("{}", style(&test.testcase).red()));
+            }
+        }
+    }
+
+    fn shard_exited(&mut self, exit: &ShardExit) {
+        if exit.error.is_some() {
+            self.shard_errors.push(exit.clone());
+        }
+    }
+
+    fn run_finished(&mut self, results: &[Test], summary: Option<&RunSummary>) {
+        for progress_shard in self.progress_shards.drain(..) {
+            progress_shard.finish_and_clear();
+        }
+        self.progress_global.finish_and_clear();
+        let _ = self.progress.clear();
+
+        // When stress testing only the last iteration is of interest.
+        if self.stress_iteration.is_some() && summary.is_none() {
+            return;
+        }
+
+        let stats = ShardStats::new(results.to_vec());
+        let failed = !stats.failed_tests.is_empty() || !self.shard_errors.is_empty();
+
+        for exit in self.shard_errors.drain(..) {
+            print_shard_error(&exit, self.verbosity);
+        }
+
+        print_summary(&stats, self.verbosity, self.show_executables);
+
+        match self.stress_iteration {
+            Some(iteration) if failed => {
+                let message = format!("Failed in stress iteration {iteration}");
+                println!("{}", style(message).bold().red());
+            }
+            Some(iteration) if self.verbosity > 0 => {
+                let message = format!("Passed {iteration} stress iterations");
+                println!("{}", style(message).bold().green());
+            }
+            _ => {}
+        }
+
+        let Some(summary) = summary else {
+            return;
+        };
+
+        print_flaky(summary, self.show_executables);
+        print_incomplete(summary, results.len());
+
+        if self.verbosity > 0 && self.slowest > 0 {
+            print_slowest(summary, self.slowest, self.show_executables);
+        }
+    }
+}
+
+/// Print the results of a single run
+///
+/// With `show_executables` the logs of failed tests are preceded by the path of their executable.
+fn print_summary(stats: &ShardStats, verbosity: u64, show_executables: bool) {
+    if stats.failed_tests.is_empty() {
+        if verbosity > 0 {
+            let message = format!(
+                "{} tests passed{}",
+                stats.num_passed,
+                stats.skipped_suffix()
+            );
+            println!("{}", style(message).bold().green());
+        }
+        return;
+    }
+
+    if verbosity <= 2 {
+        for test in &stats.failed_tests {
+            if let Event::Terminal { status, log, .. } = &test.event {
+                if status.is_failed() {
+                    if let (true, Some(exe)) = (show_executables, &test.executable) {
+                        println!("{}", style(exe.display()).bold());
+                    }
+                    for line in log {
+                        println!("{line}");
+                    }
+                }
+            }
+        }
+    }
+    let message = format!(
+        "{} out of {} tests failed{}",
+        stats.num_failed(),
+        stats.num_tests(),
+        stats.skipped_suffix()
+    );
+    println!("{}", style(message).bold().red());
+}
+
+/// Print an invocation of a test executable which failed outside of any test
+fn print_shard_error(exit: &ShardExit, verbosity: u64) {
+    if verbosity <= 2 {
+        for line in &exit.log {
+            println!("{line}");
+        }
+    }
+
+    if let Some(error) = &exit.error {
+        let message = format!("{} {error} outside of any test", exit.executable.display());
+        println!("{}", style(message).bold().red());
+    }
+}
+
+/// Print the tests which failed but passed when repeated
+fn print_flaky(summary: &RunSummary, show_executables: bool) {
+    let flaky = summary.with_outcome(Outcome::Flaky).collect::<Vec<_>>();
+    if flaky.is_empty() {
+        return;
+    }
+
+    println!(
+        "{}",
+        style(format!("{} flaky tests", flaky.len()))
+            .bold()
+            .yellow()
+    );
+    for test in flaky {
+        let failed = test
+            .attempts
+            .iter()
+            .filter(|attempt| attempt.status.is_failed())
+            .count();
+        let name = if show_executables {
+            format!("{} {}", test.id.executable.display(), test.id.testcase)
+        } else {
+            test.id.testcase.clone()
+        };
+        println!(
+            "{name} (failed {failed} out of {} attempts)",
+            test.attempts.len()
+        );
+    }
+}
+
+/// Print the tests which went missing or reported results unexpectedly in the last run
+///
+/// This mostly serves to validate that we did not accidentally drop test results.
+fn print_incomplete(summary: &RunSummary, num_reported: usize) {
+    if summary.missing.is_empty() && summary.unexpected.is_empty() {
+        return;
+    }
+
+    if summary.timed_out {
+        eprintln!("Test run timed out");
+    }
+
+    let num_expected =
+        (num_reported + summary.missing.len()).saturating_sub(summary.unexpected.len());
+    eprintln!("Expected {num_expected} tests but only saw results from {num_reported}");
+    for id in &summary.missing {
+        eprintln!("Missing result of {}", id.testcase);
+    }
+    for id in &summary.unexpected {
+        eprintln!("Unexpected result of {}", id.testcase);
+    }
+}
+
+/// Print the `n` slowest tests by the longest of their attempts
+///
+/// With `show_executables` tests are preceded by the path of their executable.
+fn print_slowest(summary: &RunSummary, n: usize, show_executables: bool) {
+    let mut durations = summary
+        .tests
+        .iter()
+        .filter_map(|test| {
+            let duration = test
+                .attempts
+                .iter()
+                .filter_map(|attempt| attempt.duration)
+                .max()?;
+            Some((duration, test))
+        })
+        .collect::<Vec<_>>();
+
+    if durations.is_empty() {
+        return;
+    }
+
+    durations.sort_by_key(|(duration, _)| Reverse(*duration));
+    durations.truncate(n);
+
+    println!(
+        "{}",
+        style(format!("Slowest {} tests", durations.len())).bold()
+    );
+    for (duration, test) in durations {
+        let name = if show_executables {
+            format!("{} {}", test.id.executable.display(), test.id.testcase)
+        } else {
+            test.id.testcase.clone()
+        };
+        println!("{:>10} ms  {name}", duration.as_millis());
+    }
+}
+
+/// Progress bar style with the given template
+///
+/// Our templates are fixed, so should one be invalid we fall back to the default style instead of
+/// failing the run.
+fn progress_style(template: &str) -> ProgressStyle {
+    ProgressStyle::default_spinner()
+        .template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_spinner())
+}
@@ -0,0 +1,428 @@
+use {
+    crate::{exec::Work, filter::matches_gtest_filter, Error, Result},
+    std::{
+        collections::{BTreeMap, BTreeSet, VecDeque},
+        net::{Ipv4Addr, TcpListener},
+        ops::RangeInclusive,
+        path::PathBuf,
+        sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    },
+};
+
+/// Tests by test executable
+type Tests = Vec<(PathBuf, Vec<String>)>;
+
+/// Local ports handed out to invocations of test executables
+///
+/// Every invocation gets `per_shard` consecutive ports from `range` which are not handed out to
+/// any other running invocation, and which could be bound when the invocation started. The first
+/// port and the number of ports are exported to the test executable as `GTEST_RUNNER_PORT_BASE`
+/// and `GTEST_RUNNER_PORT_COUNT`.
+#[derive(Clone, Debug)]
+pub struct Ports {
+    pub range: RangeInclusive<u16>,
+    pub per_shard: u16,
+}
+
+impl Ports {
+    fn num_ranges(&self) -> usize {
+        self.range.len() / usize::from(self.per_shard.max(1))
+    }
+
+    /// Ports of the range with the given index
+    fn range(&self, index: usize) -> RangeInclusive<u16> {
+        let per_shard = usize::from(self.per_shard.max(1));
+        let first = usize::from(*self.range.start()) + index * per_shard;
+
+        // Ranges are only created within `range`, so the ports fit.
+        let first = u16::try_from(first).unwrap_or(u16::MAX);
+        first..=first.saturating_add(self.per_shard.max(1) - 1)
+    }
+}
+
+/// A named resource which tests matching a gtest filter `pattern` use exclusively
+///
+/// An invocation of a test executable running any matching test holds the resource, so matching
+/// tests never run concurrently with each other.
+#[derive(Clone, Debug)]
+pub struct Resource {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// Names of the resources used by any of the given tests
+pub(crate) fn used(resources: &[Resource], tests: &[String]) -> BTreeSet<String> {
+    resources
+        .iter()
+        .filter(|resource| {
+            tests
+                .iter()
+                .any(|test| matches_gtest_filter(&resource.pattern, test))
+        })
+        .map(|resource| resource.name.clone())
+        .collect()
+}
+
+/// Split the tests of every executable into those using no resources, and groups of tests using
+/// the same resources
+///
+/// Scheduling the groups separately keeps tests which do not use a resource from waiting for it.
+pub(crate) fn split(resources: &[Resource], tests: &[(PathBuf, Vec<String>)]) -> (Tests, Tests) {
+    let mut free = vec![];
+    let mut exclusive = vec![];
+    for (executable, tests) in tests {
+        let mut groups: Vec<(BTreeSet<String>, Vec<String>)> = vec![];
+        for test in tests {
+            let used = used(resources, std::slice::from_ref(test));
+            match groups.iter_mut().find(|(group, _)| *group == used) {
+                Some((_, tests)) => tests.push(test.clone()),
+                None => groups.push((used, vec![test.clone()])),
+            }
+        }
+
+        for (used, tests) in groups {
+            if used.is_empty() {
+                free.push((executable.clone(), tests));
+            } else {
+                exclusive.push((executable.clone(), tests));
+            }
+        }
+    }
+    (free, exclusive)
+}
+
+/// Split the tests of every executable into those to run in parallel and those matching any of
+/// the gtest filter `patterns`, which are to be run serially
+pub(crate) fn split_serial(
+    patterns: &[String],
+    tests: &[(PathBuf, Vec<String>)],
+) -> (Tests, Tests) {
+    let mut parallel = vec![];
+    let mut serial = vec![];
+    for (executable, tests) in tests {
+        let (matching, other): (Vec<_>, Vec<_>) = tests.iter().cloned().partition(|test| {
+            patterns
+                .iter()
+                .any(|pattern| matches_gtest_filter(pattern, test))
+        });
+        parallel.push((executable.clone(), other));
+        if !matching.is_empty() {
+            serial.push((executable.clone(), matching));
+        }
+    }
+    (parallel, serial)
+}
+
+/// Hands out queued work to jobs together with the ports and resources it needs
+///
+/// Work is handed out in queue order, skipping work which cannot start yet because other
+/// invocations hold its resources or its test executable already runs as many invocations as its
+/// jobs cap allows, so jobs only wait if none of the queued work can start.
+pub(crate) struct Allocator {
+    ports: Option<Ports>,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // Indices of the port ranges in use.
+    ranges: BTreeSet<usize>,
+    resources: BTreeSet<String>,
+    // Number of running invocations by test executable.
+    invocations: BTreeMap<PathBuf, usize>,
+    queue: VecDeque<Queued>,
+    // Whether no further work is handed out.
+    stopped: bool,
+}
+
+/// Work waiting to be handed out, with the jobs cap of its executable and the resources it uses
+struct Queued {
+    work: Work,
+    jobs: Option<usize>,
+    resources: BTreeSet<String>,
+}
+
+/// Ports and resources held by an invocation until dropped
+pub(crate) struct Lease<'a> {
+    allocator: &'a Allocator,
+    executable: PathBuf,
+    range: Option<usize>,
+    resources: BTreeSet<String>,
+}
+
+impl Lease<'_> {
+    /// First port and number of ports of the leased port range, if any
+    pub(crate) fn ports(&self) -> Option<(u16, u16)> {
+        let ports = self.allocator.ports.as_ref()?;
+        let range = ports.range(self.range?);
+        Some((*range.start(), range.len() as u16))
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut state = self.allocator.lock();
+        if let Some(range) = self.range {
+            state.ranges.remove(&range);
+        }
+        for resource in &self.resources {
+            state.resources.remove(resource);
+        }
+        if let Some(invocations) = state.invocations.get_mut(&self.executable) {
+            *invocations -= 1;
+        }
+        self.allocator.released.notify_all();
+    }
+}
+
+impl Allocator {
+    pub(crate) fn new(ports: Option<Ports>) -> Allocator {
+        Allocator {
+            ports,
+            state: Mutex::default(),
+            released: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue `work` which uses `resources`, running at most `jobs` invocations of its executable
+    /// at the same time
+    pub(crate) fn push(&self, work: Work, jobs: Option<usize>, resources: BTreeSet<String>) {
+        let mut state = self.lock();
+        if state.stopped {
+            return;
+        }
+        state.queue.push_back(Queued {
+            work,
+            jobs,
+            resources,
+        });
+        self.released.notify_all();
+    }
+
+    /// Stop handing out work, dropping all queued work and work queued later
+    ///
+    /// Invocations which already started keep their leases until they finish.
+    pub(crate) fn stop(&self) {
+        let mut state = self.lock();
+        state.stopped = true;
+        state.queue.clear();
+        self.released.notify_all();
+    }
+
+    /// Take the first queued work which can start, with a lease of a port range and its resources
+    ///
+    /// This waits until other invocations released what is needed if none of the queued work can
+    /// start, and returns `None` once the queue is empty. If no port range is free even though
+    /// none is in use, an error is returned instead.
+    pub(crate) fn next(&self) -> Result<Option<(Work, Lease<'_>)>> {
+        let mut state = self.lock();
+        loop {
+            if state.queue.is_empty() {
+                return Ok(None);
+            }
+
+            let startable = state.queue.iter().position(|queued| {
+                let invocations = state
+                    .invocations
+                    .get(&queued.work.executable)
+                    .copied()
+                    .unwrap_or(0);
+                state.resources.is_disjoint(&queued.resources)
+                    && queued.jobs.is_none_or(|jobs| invocations < jobs)
+            });
+
+            if let Some(index) = startable {
+                // The index of the port range to lease if ports are handed out, or `None` if no
+                // range is available right now.
+                let range = match &self.ports {
+                    None => Some(None),
+                    Some(ports) => (0..ports.num_ranges())
+                        .filter(|index| !state.ranges.contains(index))
+                        .find(|&index| ports.range(index).all(bindable))
+                        .map(Some),
+                };
+
+                match (range, &self.ports) {
+                    (Some(range), _) => {
+                        let Some(Queued {
+                            work, resources, ..
+                        }) = state.queue.remove(index)
+                        else {
+                            unreachable!("queued work was just found");
+                        };
+                        state.ranges.extend(range);
+                        state.resources.extend(resources.iter().cloned());
+                        *state
+                            .invocations
+                            .entry(work.executable.clone())
+                            .or_default() += 1;
+                        let lease = Lease {
+                            allocator: self,
+                            executable: work.executable.clone(),
+                            range,
+                            resources,
+                        };
+                        return Ok(Some((work, lease)));
+                    }
+                    (None, Some(ports)) if state.ranges.is_empty() => {
+                        return Err(Error::NoFreePorts {
+                            first: *ports.range.start(),
+                            last: *ports.range.end(),
+                            per_shard: ports.per_shard,
+                        });
+                    }
+                    (None, _) => {}
+                }
+            }
+
+            state = self
+                .released
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+fn bindable(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+#[test]
+fn test_split() {
+    let resources = [Resource {
+        name: "db".into(),
+        pattern: "Db*".into(),
+    }];
+    let tests = |tests: &[&str]| {
+        tests
+            .iter()
+            .map(|test| test.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        (
+            vec![(PathBuf::from("/exe"), tests(&["A.a", "B.b"]))],
+            vec![(PathBuf::from("/exe"), tests(&["Db.a", "Db.b"]))],
+        ),
+        split(
+            &resources,
+            &[("/exe".into(), tests(&["A.a", "Db.a", "B.b", "Db.b"]))]
+        )
+    );
+    assert_eq!(
+        BTreeSet::from(["db".to_string()]),
+        used(&resources, &tests(&["A.a", "Db.a"]))
+    );
+
+    assert_eq!(
+        (
+            vec![(PathBuf::from("/exe"), tests(&["A.a"]))],
+            vec![(PathBuf::from("/exe"), tests(&["Db.a", "Dev.b"]))]
+        ),
+        split_serial(
+            &["Db.*".into(), "Dev.*".into()],
+            &[("/exe".into(), tests(&["A.a", "Db.a", "Dev.b"]))]
+        )
+    );
+}
+
+#[test]
+fn test_allocator() {
+    use std::{sync::Arc, thread, time::Duration};
+
+    let work = |executable: &str| Work::tests(executable.into(), vec![]);
+
+    // Find a range of ports which is free right now.
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let first = listener.local_addr().unwrap().port();
+    drop(listener);
+    let Some(last) = first.checked_add(5) else {
+        return;
+    };
+
+    let allocator = Allocator::new(Some(Ports {
+        range: first..=last,
+        per_shard: 2,
+    }));
+    for _ in 0..4 {
+        allocator.push(work("/exe"), None, BTreeSet::new());
+    }
+
+    // Concurrent leases get disjoint ranges, and released ranges are reused.
+    let (_, a) = allocator.next().unwrap().unwrap();
+    let (_, b) = allocator.next().unwrap().unwrap();
+    assert_eq!(Some((first, 2)), a.ports());
+    assert_eq!(Some((first + 2, 2)), b.ports());
+    drop(a);
+    assert_eq!(
+        Some((first, 2)),
+        allocator.next().unwrap().unwrap().1.ports()
+    );
+
+    // Ranges with ports bound by someone else are skipped.
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, first)).ok();
+    if listener.is_some() {
+        assert_eq!(
+            Some((first + 4, 2)),
+            allocator.next().unwrap().unwrap().1.ports()
+        );
+    }
+    drop(listener);
+    drop(b);
+
+    // Work whose resources are held or whose executable runs at its jobs cap is skipped.
+    let allocator = Arc::new(Allocator::new(None));
+    let db = BTreeSet::from(["db".to_string()]);
+    allocator.push(work("/db"), None, db.clone());
+    allocator.push(work("/db"), None, db);
+    allocator.push(work("/capped"), Some(1), BTreeSet::new());
+    allocator.push(work("/capped"), Some(1), BTreeSet::new());
+    allocator.push(work("/other"), None, BTreeSet::new());
+
+    let next = || {
+        let (work, lease) = allocator.next().unwrap().unwrap();
+        assert_eq!(None, lease.ports());
+        (work.executable, lease)
+    };
+    let (db, db_lease) = next();
+    let (capped, capped_lease) = next();
+    let (other, other_lease) = next();
+    assert_eq!(
+        vec![
+            PathBuf::from("/db"),
+            PathBuf::from("/capped"),
+            PathBuf::from("/other")
+        ],
+        vec![db, capped, other]
+    );
+
+    // Only once none of the queued work can start, jobs wait for it.
+    let waiting = {
+        let allocator = allocator.clone();
+        thread::spawn(move || allocator.next().unwrap().map(|(work, _)| work.executable))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!waiting.is_finished());
+    drop(capped_lease);
+    assert_eq!(Some(PathBuf::from("/capped")), waiting.join().unwrap());
+
+    drop(db_lease);
+    assert_eq!(
+        PathBuf::from("/db"),
+        allocator.next().unwrap().unwrap().0.executable
+    );
+    assert!(allocator.next().unwrap().is_none());
+
+    // Once stopped no work is handed out anymore.
+    allocator.push(work("/other"), None, BTreeSet::new());
+    allocator.stop();
+    allocator.push(work("/other"), None, BTreeSet::new());
+    assert!(allocator.next().unwrap().is_none());
+    drop(other_lease);
+}
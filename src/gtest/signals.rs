@@ -0,0 +1,132 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Read},
+    mem,
+    os::{
+        fd::{AsRawFd, IntoRawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command},
+    ptr,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+};
+
+/// Signals which are forwarded to running test executables
+const SIGNALS: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+/// Process groups of running invocations of test executables
+static GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Write end of the pipe the signal handler reports signals through
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Process group of a running invocation of a test executable
+///
+/// Signals are forwarded to the process group until this is dropped, which must happen before the
+/// leader of the process group is reaped.
+pub(crate) struct Group(u32);
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        lock().remove(&self.0);
+    }
+}
+
+fn lock() -> MutexGuard<'static, BTreeSet<u32>> {
+    GROUPS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Spawn a test executable in its own process group
+///
+/// Tests run in their own process group so that we can kill any processes they spawned, e.g., on
+/// timeouts. Signals sent to the process group of the runner, e.g., by the terminal, then no
+/// longer reach them, so with `forward` they are forwarded to the process group.
+pub(crate) fn spawn(cmd: &mut Command) -> io::Result<(Child, Group)> {
+    cmd.process_group(0);
+
+    // Spawn while holding the lock so that a signal arriving meanwhile still reaches the test.
+    let mut groups = lock();
+    let child = cmd.spawn()?;
+    let pgid = child.id();
+    groups.insert(pgid);
+    Ok((child, Group(pgid)))
+}
+
+/// Forward `SIGHUP`, `SIGINT`, and `SIGTERM` to running test executables
+///
+/// After forwarding a signal the runner terminates with it like it would have without this.
+/// Signals which are ignored, e.g., `SIGINT` in background jobs, stay ignored. Signals are only
+/// set up to be forwarded once, further calls do nothing.
+pub(crate) fn forward() -> io::Result<()> {
+    let (mut reader, writer) = io::pipe()?;
+    if PIPE
+        .compare_exchange(-1, writer.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return Ok(());
+    }
+    let _ = writer.into_raw_fd();
+
+    for signal in SIGNALS {
+        // SAFETY: The handler only calls `write`, which is async-signal-safe, and the actions are
+        // valid for the duration of the calls.
+        unsafe {
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(signal, ptr::null(), &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if previous.sa_sigaction == libc::SIG_IGN {
+                continue;
+            }
+
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    thread::spawn(move || {
+        let mut signal = [0];
+        if reader.read_exact(&mut signal).is_err() {
+            return;
+        }
+        let signal = libc::c_int::from(signal[0]);
+
+        // Keep the lock until we terminated so that no further tests are started.
+        let groups = lock();
+
+        // SAFETY: `kill`, `signal`, and `raise` have no memory safety requirements. The process
+        // groups cannot have been reused since their leaders are only reaped once they were
+        // removed, see `Group`.
+        unsafe {
+            for pgid in groups.iter() {
+                libc::kill(-(*pgid as libc::pid_t), signal);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    });
+
+    Ok(())
+}
+
+extern "C" fn handle(signal: libc::c_int) {
+    // All forwarded signals fit into a byte.
+    let signal = signal as u8;
+
+    // SAFETY: `write` is async-signal-safe, and the buffer is valid for the duration of the call.
+    unsafe {
+        libc::write(
+            PIPE.load(Ordering::SeqCst),
+            ptr::from_ref(&signal).cast(),
+            1,
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use {
+    crate::{Event, Flaky, ShardExit, Status, Test},
+    std::{
+        collections::{BTreeSet, HashMap},
+        path::PathBuf,
+        time::Duration,
+    },
+};
+
+/// Identity of a test across attempts
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TestId {
+    /// Canonicalized path of the test executable.
+    pub executable: PathBuf,
+    pub testcase: String,
+}
+
+impl TestId {
+    pub(crate) fn of(test: &Test) -> TestId {
+        TestId {
+            executable: test.executable.clone().unwrap_or_default(),
+            testcase: test.testcase.clone(),
+        }
+    }
+}
+
+/// Terminal result of a single attempt to run a test
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    pub status: Status,
+    pub duration: Option<Duration>,
+    /// Index of the job which ran the attempt.
+    pub shard: Option<usize>,
+    pub log: Vec<String>,
+}
+
+/// Classification of a test by all its attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Skipped,
+    /// The test failed, but passed when repeated.
+    Flaky,
+    Failed,
+}
+
+/// All attempts to run a test
+#[derive(Clone, Debug)]
+pub struct TestSummary {
+    pub id: TestId,
+    /// Attempts in the order in which they were made. This is never empty.
+    pub attempts: Vec<Attempt>,
+}
+
+impl TestSummary {
+    /// Result of the last attempt
+    pub fn last(&self) -> &Attempt {
+        self.attempts
+            .last()
+            .expect("a test summary has at least one attempt")
+    }
+
+    /// Final status of the test
+    pub fn status(&self) -> &Status {
+        &self.last().status
+    }
+
+    /// Classification of the test by all its attempts
+    pub fn outcome(&self) -> Outcome {
+        let status = self.status();
+        if status.is_failed() {
+            Outcome::Failed
+        } else if self
+            .attempts
+            .iter()
+            .any(|attempt| attempt.status.is_failed())
+        {
+            Outcome::Flaky
+        } else if *status == Status::Skipped {
+            Outcome::Skipped
+        } else {
+            Outcome::Passed
+        }
+    }
+}
+
+/// Results of a test run
+#[derive(Clone, Debug, Default)]
+pub struct RunSummary {
+    /// Terminal results of all executed tests, including repeated ones, in the order in which
+    /// they finished.
+    pub results: Vec<Test>,
+    /// Every test with a result, in the order in which tests first finished.
+    pub tests: Vec<TestSummary>,
+    /// Tests which were expected to run but did not report a result in their last attempt,
+    /// e.g., because their executable crashed or the run timed out.
+    pub missing: BTreeSet<TestId>,
+    /// Tests which reported a result without being expected to run.
+    pub unexpected: BTreeSet<TestId>,
+    /// Invocations of test executables which failed outside of any test.
+    pub shard_errors: Vec<ShardExit>,
+    /// How flaky tests count towards the exit code.
+    pub flaky: Flaky,
+    /// Number of iterations when stress testing. Only the results of the last iteration are
+    /// kept, which is the failing one if any test failed.
+    pub stress_iterations: Option<u64>,
+    /// Whether the run timeout expired before all tests finished.
+    pub timed_out: bool,
+    // Index of every test in `tests`.
+    index: HashMap<TestId, usize>,
+}
+
+impl RunSummary {
+    /// Record the terminal `results` of an attempt to run the `expected` tests.
+    pub(crate) fn record(&mut self, expected: &BTreeSet<TestId>, results: &[Test]) {
+        let mut reported = BTreeSet::new();
+        for test in results {
+            let Some(id) = self.add(test) else {
+                continue;
+            };
+
+            if !expected.contains(&id) {
+                self.unexpected.insert(id.clone());
+            }
+            self.missing.remove(&id);
+            reported.insert(id);
+        }
+
+        self.missing.extend(expected.difference(&reported).cloned());
+    }
+
+    /// Add a terminal result to the attempts of its test.
+    pub(crate) fn add(&mut self, test: &Test) -> Option<TestId> {
+        let Event::Terminal {
+            status,
+            log,
+            duration,
+        } = &test.event
+        else {
+            return None;
+        };
+
+        let id = TestId::of(test);
+        let attempt = Attempt {
+            status: status.clone(),
+            duration: *duration,
+            shard: test.shard,
+            log: log.clone(),
+        };
+        match self.index.get(&id) {
+            Some(&index) => self.tests[index].attempts.push(attempt),
+            None => {
+                self.index.insert(id.clone(), self.tests.len());
+                self.tests.push(TestSummary {
+                    id: id.clone(),
+                    attempts: vec![attempt],
+                });
+            }
+        }
+        self.results.push(test.clone());
+
+        Some(id)
+    }
+
+    /// Tests with the given outcome
+    pub fn with_outcome(&self, outcome: Outcome) -> impl Iterator<Item = &TestSummary> {
+        self.tests
+            .iter()
+            .filter(move |test| test.outcome() == outcome)
+    }
+
+    /// Number of tests counted as failed, including flaky tests if they count as failed
+    pub fn num_failed(&self) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| match test.outcome() {
+                Outcome::Failed => true,
+                Outcome::Flaky => self.flaky == Flaky::Fail,
+                Outcome::Passed | Outcome::Skipped => false,
+            })
+            .count()
+    }
+
+    /// Exit code for the run
+    ///
+    /// This is the number of failed tests and invocations which failed outside of any test, or 1
+    /// if tests went missing or unexpected tests reported results. Since only the low 8 bits of
+    /// an exit status are seen by the parent process the code is at most 255.
+    pub fn exit_code(&self) -> i32 {
+        if !self.missing.is_empty() || !self.unexpected.is_empty() {
+            return 1;
+        }
+
+        i32::try_from(self.num_failed() + self.shard_errors.len())
+            .unwrap_or(i32::MAX)
+            .min(255)
+    }
+}
+
+#[test]
+fn test_record() {
+    let id = |testcase: &str| TestId {
+        executable: "/exe".into(),
+        testcase: testcase.into(),
+    };
+    let test = |testcase: &str, status, shard| Test {
+        event: Event::Terminal {
+            status,
+            log: vec![format!("[ RUN      ] {testcase}")],
+            duration: None,
+        },
+        testcase: testcase.into(),
+        shard: Some(shard),
+        executable: Some("/exe".into()),
+    };
+
+    let mut summary = RunSummary::default();
+    summary.record(
+        &[id("A.a"), id("A.b"), id("A.c")].into(),
+        &[
+            test("A.a", Status::Ok, 0),
+            test("A.b", Status::Failed, 1),
+            test("A.d", Status::Ok, 1),
+        ],
+    );
+    assert_eq!(BTreeSet::from([id("A.c")]), summary.missing);
+    assert_eq!(BTreeSet::from([id("A.d")]), summary.unexpected);
+    assert_eq!(1, summary.num_failed());
+    assert_eq!(1, summary.exit_code());
+
+    // Repeated tests keep their history, and their last attempt decides their status.
+    summary.record(&[id("A.b")].into(), &[test("A.b", Status::Ok, 2)]);
+    assert_eq!(3, summary.tests.len());
+    let b = &summary.tests[1];
+    assert_eq!(id("A.b"), b.id);
+    assert_eq!(
+        vec![Status::Failed, Status::Ok],
+        b.attempts
+            .iter()
+            .map(|attempt| attempt.status.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(Some(2), b.last().shard);
+    assert_eq!(4, summary.results.len());
+
+    // Tests which failed before passing are flaky.
+    assert_eq!(Outcome::Flaky, b.outcome());
+    assert_eq!(
+        vec![&id("A.a"), &id("A.d")],
+        summary
+            .with_outcome(Outcome::Passed)
+            .map(|test| &test.id)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(0, summary.num_failed());
+    summary.flaky = Flaky::Fail;
+    assert_eq!(1, summary.num_failed());
+
+    // Exit codes do not wrap around to success.
+    let mut summary = RunSummary::default();
+    let failed = (0..256)
+        .map(|i| test(&format!("A.f{i}"), Status::Failed, 0))
+        .collect::<Vec<_>>();
+    summary.record(&BTreeSet::new(), &failed);
+    summary.unexpected.clear();
+    assert_eq!(256, summary.num_failed());
+    assert_eq!(255, summary.exit_code());
+}
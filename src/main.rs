@@ -1,11 +1,10 @@
 use {
     anyhow::Result,
-    clap::Parser,
     gtest::{exec::exec, opt::Opt},
 };
 
 fn main() -> Result<()> {
-    let opt = Opt::parse();
+    let opt = Opt::parse_with_config_file()?;
 
     std::process::exit(exec(&opt)?);
 }
@@ -1,9 +1,12 @@
-use std::env;
+use std::{env, time::Duration};
 
 use gtest::{
     exec::exec,
     opt::{Opt, RunMode},
+    Error, Event, Filter, Flaky, Outcome, Overrides, Ports, Reporter, Rerun, RunConfig, RunSummary,
+    Schedule, ShardExit, Status, Stress, Test, Timeouts,
 };
+use regex::Regex;
 use rstest::{fixture, rstest};
 
 #[fixture]
@@ -11,18 +14,716 @@ fn exe() -> &'static str {
     // Activate sample data mode for the executable.
     env::set_var("GTEST_RUNNER_SAMPLE_DATA", "true");
 
+    // Failed invocations keep their temporary directories, so keep them with our build artifacts.
+    env::set_var("TMPDIR", env!("CARGO_TARGET_TMPDIR"));
+
     // The test executable is the runner binary.
     env!("CARGO_BIN_EXE_gtest-runner")
 }
 
+#[fixture]
+fn tmp() -> tempfile::TempDir {
+    // A fresh directory with our build artifacts which is removed once the test finished, even if
+    // it failed.
+    tempfile::Builder::new()
+        .prefix("gtest-runner-")
+        .tempdir_in(env!("CARGO_TARGET_TMPDIR"))
+        .expect("Could not create temporary directory")
+}
+
+#[rstest]
+fn run1(
+    exe: &str,
+    #[values(Schedule::Shard, Schedule::Queue, Schedule::Balanced)] schedule: Schedule,
+) {
+    let config = RunConfig::new([exe]).jobs(1).schedule(schedule);
+    assert_eq!(0, gtest::run(&config, &mut []).unwrap().exit_code());
+}
+
+#[rstest]
+fn run2(
+    exe: &str,
+    #[values(Schedule::Shard, Schedule::Queue, Schedule::Balanced)] schedule: Schedule,
+) {
+    let config = RunConfig::new([exe]).jobs(2).schedule(schedule);
+    assert_eq!(0, gtest::run(&config, &mut []).unwrap().exit_code());
+}
+
+#[rstest]
+fn filtered(
+    exe: &str,
+    #[values(Schedule::Shard, Schedule::Queue, Schedule::Balanced)] schedule: Schedule,
+    #[values(
+        Filter::new(Some("*-NOPE.NOPE1".into()), None),
+        Filter::new(None, Some(Regex::new("0$").unwrap())),
+        Filter::new(Some("NOPE.*".into()), Some(Regex::new("0$").unwrap()))
+    )]
+    filter: Filter,
+) {
+    let config = RunConfig::new([exe])
+        .jobs(2)
+        .schedule(schedule)
+        .filter(filter);
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+
+    assert_eq!(
+        vec!["NOPE.NOPE0"],
+        summary
+            .results
+            .iter()
+            .map(|result| result.testcase.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    // The filter is not applied through the environment of the runner.
+    assert!(env::var_os("GTEST_FILTER").is_none());
+}
+
+#[rstest]
+fn run_multiple(
+    exe: &str,
+    tmp: tempfile::TempDir,
+    #[values(Schedule::Shard, Schedule::Queue, Schedule::Balanced)] schedule: Schedule,
+) {
+    // Create a second test executable with a different path.
+    let exe2 = tmp.path().join("gtest-runner");
+    std::fs::hard_link(exe, &exe2).expect("Could not link test executable");
+
+    let config = RunConfig::new([exe.into(), exe2.clone()])
+        .jobs(3)
+        .schedule(schedule);
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert_eq!(4, summary.results.len());
+}
+
+#[rstest]
+fn run_duplicate(exe: &str) {
+    // Test executables given multiple times are only run once.
+    let summary = gtest::run(&RunConfig::new([exe, exe]).jobs(3), &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert_eq!(2, summary.results.len());
+}
+
+#[rstest]
+fn reporter(
+    exe: &str,
+    #[values(Schedule::Shard, Schedule::Queue, Schedule::Balanced)] schedule: Schedule,
+) {
+    #[derive(Default)]
+    struct Counts {
+        runs_started: usize,
+        tests_started: usize,
+        tests_finished: usize,
+        shards_exited: usize,
+        runs_finished: Vec<(usize, bool)>,
+    }
+
+    impl Reporter for Counts {
+        fn run_started(&mut self, _num_tests: usize, _jobs: usize) {
+            self.runs_started += 1;
+        }
+
+        fn test_started(&mut self, _test: &Test) {
+            self.tests_started += 1;
+        }
+
+        fn test_finished(&mut self, _test: &Test) {
+            self.tests_finished += 1;
+        }
+
+        fn shard_exited(&mut self, _exit: &ShardExit) {
+            self.shards_exited += 1;
+        }
+
+        fn run_finished(&mut self, results: &[Test], summary: Option<&RunSummary>) {
+            self.runs_finished.push((results.len(), summary.is_some()));
+        }
+    }
+
+    let mut counts = Counts::default();
+    let config = RunConfig::new([exe]).jobs(2).schedule(schedule);
+    assert_eq!(
+        0,
+        gtest::run(&config, &mut [&mut counts]).unwrap().exit_code()
+    );
+
+    assert_eq!(1, counts.runs_started);
+    assert_eq!(2, counts.tests_started);
+    assert_eq!(2, counts.tests_finished);
+    assert!(counts.shards_exited > 0);
+    assert_eq!(vec![(2, true)], counts.runs_finished);
+}
+
 #[rstest]
-fn run1(exe: &str) {
-    assert_eq!(0, gtest::run(exe, None, 1, 0, 0).unwrap());
+fn environment(exe: &str) {
+    // Environment overrides only apply to the test executables.
+    let config = RunConfig::new([exe])
+        .env("GTEST_RUNNER_SAMPLE_DATA_SKIP", "NOPE.NOPE1")
+        .working_dir(env!("CARGO_TARGET_TMPDIR"));
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert!(env::var_os("GTEST_RUNNER_SAMPLE_DATA_SKIP").is_none());
+
+    let skipped = summary
+        .results
+        .iter()
+        .filter(|result| {
+            matches!(
+                result.event,
+                Event::Terminal {
+                    status: Status::Skipped,
+                    ..
+                }
+            )
+        })
+        .map(|result| result.testcase.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["NOPE.NOPE1"], skipped);
+}
+
+#[rstest]
+fn isolation(exe: &str, tmp: tempfile::TempDir) {
+    let base = tmp.path();
+    let config = RunConfig::new([exe])
+        .jobs(1)
+        .env("TMPDIR", base)
+        .isolate_working_dir(true);
+
+    // Directories of failed invocations are kept and mentioned in the logs of failed tests.
+    let summary = gtest::run(
+        &config
+            .clone()
+            .env("GTEST_RUNNER_SAMPLE_DATA_FAIL", "NOPE.NOPE1"),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(1, summary.exit_code());
+    let failed = &summary.tests[1];
+    assert_eq!("NOPE.NOPE1", failed.id.testcase);
+    let kept = failed
+        .last()
+        .log
+        .last()
+        .and_then(|line| line.strip_prefix("Temporary files kept in "))
+        .map(std::path::PathBuf::from)
+        .unwrap();
+    assert_eq!(Some(base), kept.parent());
+    assert!(kept.join("tmp").join("NOPE.NOPE0").exists());
+    assert!(kept.join("tmp").join("NOPE.NOPE1").exists());
+    assert!(kept.join("work").is_dir());
+
+    // Directories of successful invocations are removed.
+    assert_eq!(0, gtest::run(&config, &mut []).unwrap().exit_code());
+    assert_eq!(1, std::fs::read_dir(base).unwrap().count());
+}
+
+#[rstest]
+fn wrapper(exe: &str) {
+    // The wrapper prefixes invocations which run tests ...
+    let config = RunConfig::new([exe]).wrapper(["env", "GTEST_RUNNER_SAMPLE_DATA_FAIL=NOPE.NOPE1"]);
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(1, summary.num_failed());
+
+    // ... as well as invocations which list tests.
+    let config = RunConfig::new([exe]).wrapper(["false"]);
+    assert!(matches!(
+        gtest::run(&config, &mut []),
+        Err(Error::Listing { .. })
+    ));
+}
+
+#[rstest]
+fn ports(exe: &str) {
+    // Every invocation gets a range of ports, exported to the test executable.
+    let config = RunConfig::new([exe]).jobs(2).ports(Ports {
+        range: 40000..=40099,
+        per_shard: 10,
+    });
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    for test in &summary.tests {
+        let base = test
+            .last()
+            .log
+            .iter()
+            .find_map(|line| line.strip_prefix("Using ports from "))
+            .and_then(|base| base.parse::<u16>().ok())
+            .unwrap();
+        assert!((40000..40100).contains(&base));
+        assert_eq!(0, base % 10);
+    }
+
+    // Tests using the same resource run one after the other, in a single invocation.
+    let config = RunConfig::new([exe]).jobs(2).resource("db", "NOPE.*");
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert_eq!(2, summary.tests.len());
+
+    let summary = gtest::run(&config.env("GTEST_RUNNER_SAMPLE_DATA_EXIT", "3"), &mut []).unwrap();
+    assert_eq!(2, summary.tests.len());
+    assert_eq!(1, summary.shard_errors.len());
+}
+
+#[rstest]
+fn serial(exe: &str) {
+    // Serial tests run after all other tests in a single invocation.
+    let config = RunConfig::new([exe]).jobs(2).serial("NOPE.NOPE0");
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert_eq!(
+        vec!["NOPE.NOPE1", "NOPE.NOPE0"],
+        summary
+            .results
+            .iter()
+            .map(|test| test.testcase.as_str())
+            .collect::<Vec<_>>()
+    );
 }
 
 #[rstest]
-fn run2(exe: &str) {
-    assert_eq!(0, gtest::run(exe, None, 2, 0, 0).unwrap());
+fn overrides(exe: &str, tmp: tempfile::TempDir) {
+    // Overrides replace the filter of the run and add to its environment.
+    let config = RunConfig::new([exe]).filter(Filter::new(Some("-*".into()), None));
+    let overrides = Overrides {
+        filter: Some(Filter::new(Some("NOPE.NOPE1".into()), None)),
+        env: vec![("GTEST_RUNNER_SAMPLE_DATA_FAIL".into(), "NOPE.NOPE1".into())],
+        jobs: Some(1),
+        ..Overrides::default()
+    };
+    let config = config.overrides(exe, overrides);
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(1, summary.tests.len());
+    assert_eq!(Status::Failed, *summary.tests[0].status());
+
+    // Later overrides of the same executable only replace the settings they set.
+    let summary = gtest::run(
+        &config.overrides(
+            exe,
+            Overrides {
+                timeout: Some(Duration::from_secs(10)),
+                ..Overrides::default()
+            },
+        ),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(1, summary.tests.len());
+    assert_eq!(Status::Failed, *summary.tests[0].status());
+
+    // Executables can be listed with their overrides in a manifest. Its filter replaces the
+    // filter of the run, and with a jobs cap of 1 the timed out tests run one after the other.
+    // Overrides from the config file apply unless the manifest sets them.
+    let manifest = tmp.path().join("manifest.toml");
+    let report = tmp.path().join("report.xml");
+    std::fs::write(
+        &manifest,
+        format!(
+            r#"
+                [executables."{exe}"]
+                filter = "NOPE.*"
+                timeout = "1s"
+                jobs = 1
+                args = ["--slowest=0"]
+            "#
+        ),
+    )
+    .unwrap();
+    let opt = Opt {
+        jobs: Some(2),
+        filter: Some("NOPE.NOPE0".into()),
+        junit: Some(report.clone()),
+        overrides: vec![(
+            exe.into(),
+            Overrides {
+                env: vec![(
+                    "GTEST_RUNNER_SAMPLE_DATA_HANG".into(),
+                    "NOPE.NOPE0:NOPE.NOPE1".into(),
+                )],
+                timeout: Some(Duration::from_secs(30)),
+                ..Overrides::default()
+            },
+        )],
+        cache_dir: Some(env!("CARGO_TARGET_TMPDIR").into()),
+        mode: RunMode {
+            manifest: Some(manifest.clone()),
+            ..RunMode::default()
+        },
+        ..Default::default()
+    };
+    let start = std::time::Instant::now();
+    let exit_code = exec(&opt);
+    let elapsed = start.elapsed();
+    assert_eq!(2, exit_code.unwrap());
+    assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+
+    let xml = std::fs::read_to_string(&report).unwrap();
+    assert!(xml.contains(r#"<testsuites tests="2" failures="2""#));
+    assert_eq!(2, xml.matches(r#"<failure message="timed out">"#).count());
+    assert_eq!(2, xml.matches("Arguments --slowest=0").count());
+}
+
+#[rstest]
+fn timeout(exe: &str) {
+    // The hanging test is killed and reported as a failure, but the remaining test of the shard
+    // still runs.
+    let timeouts = Timeouts {
+        test: Some(Duration::from_millis(500)),
+        ..Timeouts::default()
+    };
+    for schedule in [Schedule::Shard, Schedule::Queue] {
+        let config = RunConfig::new([exe])
+            .jobs(1)
+            .schedule(schedule)
+            .timeouts(timeouts)
+            .env("GTEST_RUNNER_SAMPLE_DATA_HANG", "NOPE.NOPE0");
+        let summary = gtest::run(&config, &mut []).unwrap();
+        assert_eq!(1, summary.exit_code());
+        let results = summary.results;
+
+        // Tests killed by the runner have their wall-clock time as duration.
+        let Some(Event::Terminal {
+            status: Status::Timeout,
+            duration: Some(duration),
+            ..
+        }) = results
+            .iter()
+            .find(|result| result.testcase == "NOPE.NOPE0")
+            .map(|result| &result.event)
+        else {
+            panic!("Expected timed out test with duration, got {results:?}");
+        };
+        assert!(*duration >= Duration::from_millis(500));
+    }
+
+    // Once the run timed out no further tests are started.
+    let timeouts = Timeouts {
+        run: Some(Duration::from_millis(500)),
+        ..Timeouts::default()
+    };
+    let config = RunConfig::new([exe])
+        .jobs(1)
+        .timeouts(timeouts)
+        .env("GTEST_RUNNER_SAMPLE_DATA_HANG", "NOPE.NOPE0");
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(
+        vec!["NOPE.NOPE1"],
+        summary
+            .missing
+            .iter()
+            .map(|id| id.testcase.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert!(summary.unexpected.is_empty());
+
+    // Timeouts too long to represent a deadline for never expire.
+    let timeouts = Timeouts {
+        test: Some(Duration::MAX),
+        run: Some(Duration::MAX),
+    };
+    let config = RunConfig::new([exe]).timeouts(timeouts);
+    assert_eq!(0, gtest::run(&config, &mut []).unwrap().exit_code());
+}
+
+#[rstest]
+#[case::interrupt(libc::SIGINT)]
+#[case::terminate(libc::SIGTERM)]
+fn signals(exe: &str, #[case] signal: libc::c_int) {
+    use std::{os::unix::process::ExitStatusExt, process::Command, thread};
+
+    // Tests are only found in sample data mode inside the wrapper, not in the runner itself.
+    let mut runner = Command::new(exe)
+        .args([
+            "--jobs=1",
+            "--wrapper=env GTEST_RUNNER_SAMPLE_DATA=true",
+            exe,
+        ])
+        .env_remove("GTEST_RUNNER_SAMPLE_DATA")
+        .env("GTEST_RUNNER_SAMPLE_DATA_HANG", "NOPE.NOPE0")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Wait for the hanging test, which unlike the listing of tests has no `GTEST_LIST_TESTS`.
+    let hanging = (0..100)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(100));
+            std::fs::read_dir(format!("/proc/{}/task", runner.id()))
+                .unwrap()
+                .filter_map(|task| {
+                    std::fs::read_to_string(task.unwrap().path().join("children")).ok()
+                })
+                .flat_map(|children| {
+                    children
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                })
+                .find(|pid| {
+                    std::fs::read(format!("/proc/{pid}/environ")).is_ok_and(|environ| {
+                        !environ
+                            .split(|b| *b == 0)
+                            .any(|var| var.starts_with(b"GTEST_LIST_TESTS="))
+                    })
+                })
+        })
+        .expect("Test did not start");
+
+    // The signal is forwarded to the test before the runner terminates with it.
+    // SAFETY: `kill` has no memory safety requirements.
+    unsafe {
+        libc::kill(runner.id() as libc::pid_t, signal);
+    }
+    assert_eq!(Some(signal), runner.wait().unwrap().signal());
+
+    // The killed test is reaped by init once the runner is gone.
+    let gone = (0..100).any(|_| {
+        thread::sleep(Duration::from_millis(100));
+        std::fs::read_to_string(format!("/proc/{hanging}/stat"))
+            .map_or(true, |stat| stat.split_whitespace().nth(2) == Some("Z"))
+    });
+    assert!(gone, "Test {hanging} still running");
+}
+
+#[rstest]
+fn crash(exe: &str) {
+    // The crash is reported on the test which was running, and the remaining tests of the shard
+    // are run in a new process.
+    let config = RunConfig::new([exe]).jobs(1);
+    let summary = gtest::run(
+        &config
+            .clone()
+            .env("GTEST_RUNNER_SAMPLE_DATA_CRASH", "NOPE.NOPE0"),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert!(summary.shard_errors.is_empty());
+    assert!(summary.missing.is_empty());
+    assert_eq!(
+        vec![(Status::Aborted, Some(0)), (Status::Ok, Some(0))],
+        summary
+            .tests
+            .iter()
+            .map(|test| (test.status().clone(), test.last().shard))
+            .collect::<Vec<_>>()
+    );
+    let aborted = &summary.tests[0];
+    assert_eq!("NOPE.NOPE0", aborted.id.testcase);
+    assert!(aborted
+        .last()
+        .log
+        .contains(&"[  ABORTED ] NOPE.NOPE0 (killed by SIGABRT)".to_string()));
+
+    // Failures outside of any test are reported for the invocation.
+    let summary = gtest::run(&config.env("GTEST_RUNNER_SAMPLE_DATA_EXIT", "3"), &mut []).unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(0, summary.num_failed());
+    assert_eq!(1, summary.shard_errors.len());
+    let exit = &summary.shard_errors[0];
+    assert_eq!(Some(3), exit.status.code());
+    assert_eq!(Some("exited with code 3"), exit.error.as_deref());
+    assert!(exit.log.contains(&"sample.cc:7: Failure".to_string()));
+}
+
+#[rstest]
+fn flaky(exe: &str, tmp: tempfile::TempDir) {
+    // Make one test fail on its first run.
+    let config = RunConfig::new([exe])
+        .repeat(1)
+        .env("GTEST_RUNNER_SAMPLE_DATA_FLAKY", "NOPE.NOPE1")
+        .env("GTEST_RUNNER_SAMPLE_DATA_STATE", tmp.path());
+
+    // Tests which pass when repeated are flaky, and by default do not fail the run.
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    let outcomes = summary
+        .tests
+        .iter()
+        .map(|test| (test.id.testcase.as_str(), test.outcome()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("NOPE.NOPE0", Outcome::Passed),
+            ("NOPE.NOPE1", Outcome::Flaky)
+        ],
+        outcomes
+    );
+
+    // Flaky tests can also count as failures, starting over with fresh state.
+    let state_dir = tmp.path().join("again");
+    std::fs::create_dir(&state_dir).unwrap();
+    let config = config
+        .env("GTEST_RUNNER_SAMPLE_DATA_STATE", &state_dir)
+        .flaky(Flaky::Fail);
+    assert_eq!(1, gtest::run(&config, &mut []).unwrap().exit_code());
+}
+
+#[rstest]
+fn stress(exe: &str, tmp: tempfile::TempDir) {
+    // Make one test fail on its third run.
+    let config = RunConfig::new([exe])
+        .env("GTEST_RUNNER_SAMPLE_DATA_FLAKY", "NOPE.NOPE1")
+        .env("GTEST_RUNNER_SAMPLE_DATA_FLAKY_RUN", "3")
+        .env("GTEST_RUNNER_SAMPLE_DATA_STATE", tmp.path());
+
+    // Testing stops at the first failing iteration whose results are kept.
+    let summary = gtest::run(&config.clone().stress(Stress::default()).repeat(2), &mut []).unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(Some(3), summary.stress_iterations);
+    assert_eq!(2, summary.results.len());
+    let failed = &summary.tests[1];
+    assert_eq!("NOPE.NOPE1", failed.id.testcase);
+    assert_eq!(1, failed.attempts.len());
+    assert!(failed
+        .last()
+        .log
+        .contains(&"sample.cc:42: Failure in run 3".to_string()));
+
+    // No further tests are started after the first failure.
+    let summary = gtest::run(
+        &RunConfig::new([exe])
+            .jobs(1)
+            .schedule(Schedule::Queue)
+            .stress(Stress::default())
+            .env("GTEST_RUNNER_SAMPLE_DATA_FAIL", "NOPE.NOPE0"),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(1, summary.exit_code());
+    assert_eq!(Some(1), summary.stress_iterations);
+    assert_eq!(1, summary.results.len());
+    assert_eq!("NOPE.NOPE0", summary.results[0].testcase);
+    assert!(summary.missing.is_empty());
+
+    // Without failures testing stops at the limits.
+    let config = config.stress(Stress {
+        iterations: Some(2),
+        ..Stress::default()
+    });
+    let summary = gtest::run(&config, &mut []).unwrap();
+    assert_eq!(0, summary.exit_code());
+    assert_eq!(Some(2), summary.stress_iterations);
+    assert_eq!(2, summary.results.len());
+
+    // Durations too long to represent a deadline for are no limit.
+    let summary = gtest::run(
+        &config.stress(Stress {
+            iterations: Some(1),
+            duration: Some(Duration::MAX),
+        }),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(Some(1), summary.stress_iterations);
+}
+
+#[rstest]
+fn failed(exe: &str, tmp: tempfile::TempDir) {
+    let cache_dir = tmp.path();
+
+    // Make one test fail.
+    let failing = RunConfig::new([exe])
+        .jobs(1)
+        .cache_dir(cache_dir)
+        .env("GTEST_RUNNER_SAMPLE_DATA_FAIL", "NOPE.NOPE1");
+    let run = |config: &RunConfig, rerun| {
+        let summary = gtest::run(&config.clone().rerun(rerun), &mut []).unwrap();
+        (summary.exit_code(), summary.results)
+    };
+    let testcases = |results: &[Test]| {
+        results
+            .iter()
+            .map(|result| result.testcase.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // Rerunning failed tests requires a cache.
+    assert!(matches!(
+        gtest::run(&RunConfig::new([exe]).rerun(Rerun::OnlyFailed), &mut []),
+        Err(Error::MissingCacheDir)
+    ));
+
+    let (exit_code, results) = run(&failing, Rerun::All);
+    assert_eq!(1, exit_code);
+    assert_eq!(vec!["NOPE.NOPE0", "NOPE.NOPE1"], testcases(&results));
+
+    // Repeated tests keep the history of their attempts.
+    let summary = gtest::run(
+        &RunConfig::new([exe])
+            .repeat(2)
+            .env("GTEST_RUNNER_SAMPLE_DATA_FAIL", "NOPE.NOPE1"),
+        &mut [],
+    )
+    .unwrap();
+    assert_eq!(1, summary.exit_code());
+    let attempts = summary
+        .tests
+        .iter()
+        .map(|test| (test.id.testcase.as_str(), test.attempts.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![("NOPE.NOPE0", 1), ("NOPE.NOPE1", 3)], attempts);
+    assert_eq!(Status::Failed, *summary.tests[1].status());
+
+    // Tests which failed in the previous run are run first.
+    let (exit_code, results) = run(&failing, Rerun::FailedFirst);
+    assert_eq!(1, exit_code);
+    assert_eq!(vec!["NOPE.NOPE1", "NOPE.NOPE0"], testcases(&results));
+
+    // Once the test passes it is no longer considered failed.
+    let passing = RunConfig::new([exe]).jobs(1).cache_dir(cache_dir);
+    let (exit_code, results) = run(&passing, Rerun::OnlyFailed);
+    assert_eq!(0, exit_code);
+    assert_eq!(vec!["NOPE.NOPE1"], testcases(&results));
+
+    let (exit_code, results) = run(&passing, Rerun::OnlyFailed);
+    assert_eq!(0, exit_code);
+    assert!(results.is_empty());
+}
+
+#[rstest]
+fn stderr(exe: &str) {
+    let summary = gtest::run(&RunConfig::new([exe]).jobs(1), &mut []).unwrap();
+
+    // Output on stderr is attached to the log of the test which produced it.
+    for result in summary.results {
+        let Event::Terminal { log, .. } = result.event else {
+            panic!("Expected terminal event, got {:?}", result.event);
+        };
+        assert_eq!(
+            vec![
+                format!("[ RUN      ] {}", result.testcase),
+                format!("Output of {} on stderr", result.testcase),
+                format!("[       OK ] {} (0 ms)", result.testcase),
+            ],
+            log
+        );
+    }
+}
+
+#[rstest]
+fn cache(exe: &str, tmp: tempfile::TempDir) {
+    let cache_dir = tmp.path();
+
+    // Test durations are recorded and used for scheduling in later runs.
+    let config = RunConfig::new([exe])
+        .jobs(2)
+        .schedule(Schedule::Balanced)
+        .cache_dir(cache_dir);
+    for _ in 0..2 {
+        assert_eq!(0, gtest::run(&config, &mut []).unwrap().exit_code());
+    }
+
+    let timings =
+        std::fs::read_to_string(cache_dir.join("timings")).expect("Could not read timings");
+
+    assert_eq!(2, timings.lines().count());
 }
 
 #[rstest]
@@ -31,10 +732,24 @@ fn get_tests(exe: &str) {
     assert_eq!(2, num_tests.unwrap());
 }
 
+#[test]
+fn get_tests_errors() {
+    assert!(matches!(
+        gtest::exec::get_tests("/does/not/exist", false),
+        Err(Error::Spawn { .. })
+    ));
+
+    assert!(matches!(
+        gtest::exec::get_tests("false", false),
+        Err(Error::Listing { .. })
+    ));
+}
+
 #[rstest]
 fn trace(exe: &str) {
     let opt = Opt {
         trace: true,
+        cache_dir: Some(env!("CARGO_TARGET_TMPDIR").into()),
         mode: RunMode {
             test_executables: Some(vec![exe.into()]),
             ..RunMode::default()
@@ -77,3 +792,26 @@ fn trace(exe: &str) {
     assert!(size > 100, "Unexpected of small size of trace file");
     std::fs::remove_file(trace).expect("Could not remove test trace");
 }
+
+#[rstest]
+fn junit(exe: &str, tmp: tempfile::TempDir) {
+    let report = tmp.path().join("report.xml");
+
+    let opt = Opt {
+        junit: Some(report.clone()),
+        cache_dir: Some(env!("CARGO_TARGET_TMPDIR").into()),
+        mode: RunMode {
+            test_executables: Some(vec![exe.into()]),
+            ..RunMode::default()
+        },
+        ..Default::default()
+    };
+
+    assert_eq!(0, exec(&opt).expect("Could not execute test executable"));
+
+    let xml = std::fs::read_to_string(&report).expect("Could not read JUnit report");
+
+    assert!(xml.contains(r#"<testsuites tests="2" failures="0" errors="0" skipped="0""#));
+    assert!(xml.contains(r#"<testcase name="NOPE0" classname="NOPE""#));
+    assert!(xml.contains(r#"<testcase name="NOPE1" classname="NOPE""#));
+}